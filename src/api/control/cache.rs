@@ -0,0 +1,344 @@
+use std::{collections::{BTreeMap, BTreeSet}, str::from_utf8, sync::{Arc, LazyLock}};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use hyper::body::Bytes;
+use json::{JsonValue, object};
+use sled::Tree;
+use tokio::sync::Mutex;
+
+use crate::api::{control::{pubsub::validate_channel, storage::setup::get_client}, typedef::{BackendError, protocol::{ErrorCode, PacketError}}};
+
+pub const MAX_KEY_LENGTH: usize = 256;
+
+// CACHE_WRITE flags
+pub const CACHE_SHARED: u8 = 1;
+pub const CACHE_PERSISTENT: u8 = 2;
+
+/**
+* A cache entry, owner is the client that wrote it last. Only the owner can overwrite or delete
+* an entry unless it's shared, entries that are neither shared nor persistent are dropped when
+* their owner disconnects.
+*/
+#[derive(Clone)]
+pub struct CacheEntry {
+    pub payload: Bytes,
+    pub owner: Box<str>,
+    pub shared: bool,
+    pub persistent: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>
+}
+
+// Channel and key
+type Key = (Box<str>, Box<str>);
+
+struct Store {
+    entries: BTreeMap<Key, CacheEntry>,
+    // Expiry millis then key, ordered so expired entries are a range scan
+    expiries: BTreeSet<(i64, Key)>
+}
+
+// Channel, a 0 byte then the key -> encoded entry, only entries written with CACHE_PERSISTENT
+static PERSISTED: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("cache").expect("Failed to open 'cache' tree")));
+
+/**
+* The websocket cache, persistent entries are loaded back from sled on first use.
+*/
+static CACHE: LazyLock<Arc<Mutex<Store>>> = LazyLock::new(|| Arc::new(Mutex::new(Store::load())));
+
+fn persisted_key(key: &Key) -> Vec<u8> {
+    [key.0.as_bytes(), &[0], key.1.as_bytes()].concat()
+}
+
+/**
+* flags: u8, expires_at: i64 (0 if it doesn't expire), updated_at: i64, owner: u16 prefixed
+* string, then the payload.
+*/
+fn encode_entry(entry: &CacheEntry) -> Vec<u8> {
+    let flags = if entry.shared { CACHE_SHARED } else { 0 } | if entry.persistent { CACHE_PERSISTENT } else { 0 };
+    let expires_at = entry.expires_at.map(|d| d.timestamp_millis()).unwrap_or(0);
+
+    [
+        &[flags][..],
+        &expires_at.to_be_bytes(),
+        &entry.updated_at.timestamp_millis().to_be_bytes(),
+        &(entry.owner.len() as u16).to_be_bytes(),
+        entry.owner.as_bytes(),
+        &entry.payload
+    ].concat()
+}
+
+fn decode_entry(raw: &[u8]) -> Option<CacheEntry> {
+    let flags = *raw.first()?;
+    let expires_at = i64::from_be_bytes(raw.get(1..9)?.try_into().ok()?);
+    let updated_at = i64::from_be_bytes(raw.get(9..17)?.try_into().ok()?);
+    let owner_len = u16::from_be_bytes(raw.get(17..19)?.try_into().ok()?) as usize;
+    let owner = from_utf8(raw.get(19..19 + owner_len)?).ok()?;
+
+    Some(CacheEntry {
+        payload: Bytes::copy_from_slice(&raw[19 + owner_len..]),
+        owner: owner.into(),
+        shared: flags & CACHE_SHARED != 0,
+        persistent: flags & CACHE_PERSISTENT != 0,
+        expires_at: (expires_at != 0).then(|| DateTime::from_timestamp_millis(expires_at)).flatten(),
+        updated_at: DateTime::from_timestamp_millis(updated_at)?
+    })
+}
+
+fn decode_key(raw: &[u8]) -> Option<Key> {
+    let split = raw.iter().position(|b| *b == 0)?;
+
+    Some((from_utf8(&raw[..split]).ok()?.into(), from_utf8(&raw[split + 1..]).ok()?.into()))
+}
+
+impl CacheEntry {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|d| d <= now)
+    }
+
+    /**
+    * The entry without its payload, with_payload adds it as a string, null if it isn't utf-8.
+    */
+    pub fn to_json(&self, channel: &str, key: &str, with_payload: bool) -> JsonValue {
+        let mut json = object! {
+            channel: channel,
+            key: key,
+            owner: self.owner.as_ref(),
+            shared: self.shared,
+            persistent: self.persistent,
+            size: self.payload.len(),
+            expires_at: self.expires_at.map(|d| d.timestamp_millis()),
+            updated_at: self.updated_at.timestamp_millis()
+        };
+
+        if with_payload {
+            json["payload"] = from_utf8(&self.payload).ok().into();
+        }
+        json
+    }
+}
+
+impl Store {
+    /**
+    * Reads the persisted entries, expired and corrupt ones are removed from sled.
+    */
+    fn load() -> Self {
+        let mut store = Self { entries: BTreeMap::new(), expiries: BTreeSet::new() };
+        let now = Utc::now();
+
+        for item in PERSISTED.iter() {
+            let Ok((raw_key, raw_entry)) = item else { continue };
+
+            match decode_key(&raw_key).zip(decode_entry(&raw_entry)) {
+                Some((key, entry)) if !entry.is_expired(now) => store.insert(key, entry),
+                _ => {
+                    if let Err(e) = PERSISTED.remove(raw_key) {
+                        eprintln!("Failed to remove a stale cache entry: {e}");
+                    }
+                }
+            }
+        }
+        store
+    }
+
+    fn insert(&mut self, key: Key, entry: CacheEntry) {
+        if let Some(date) = entry.expires_at {
+            self.expiries.insert((date.timestamp_millis(), key.clone()));
+        }
+        self.entries.insert(key, entry);
+    }
+
+    /**
+    * Removes an entry from memory, its expiry index and sled.
+    */
+    fn remove(&mut self, key: &Key) -> Result<Option<CacheEntry>, BackendError> {
+        let Some(entry) = self.entries.remove(key) else {
+            return Ok(None);
+        };
+
+        if let Some(date) = entry.expires_at {
+            self.expiries.remove(&(date.timestamp_millis(), key.clone()));
+        }
+        if entry.persistent {
+            PERSISTED.remove(persisted_key(key))?;
+        }
+        Ok(Some(entry))
+    }
+
+    fn get(&self, key: &Key, now: DateTime<Utc>) -> Option<&CacheEntry> {
+        self.entries.get(key).filter(|entry| !entry.is_expired(now))
+    }
+}
+
+fn make_key(channel: &str, key: &str) -> Result<Key, PacketError> {
+    validate_channel(channel, false)?;
+
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(PacketError::new(ErrorCode::Malformed, &format!("Cache keys must be 1 to {MAX_KEY_LENGTH} bytes")));
+    }
+    Ok((channel.into(), key.into()))
+}
+
+fn check_owner(entry: &CacheEntry, source: &str) -> Result<(), PacketError> {
+    if !entry.shared && entry.owner.as_ref() != source {
+        return Err(PacketError::new(ErrorCode::Forbidden, &format!("The cache entry is owned by {}", entry.owner)));
+    }
+    Ok(())
+}
+
+pub async fn read_entry(channel: &str, key: &str) -> Result<Option<Bytes>, PacketError> {
+    let key = make_key(channel, key)?;
+
+    Ok(CACHE.lock().await.get(&key, Utc::now()).map(|entry| entry.payload.clone()))
+}
+
+/**
+* Writes an entry as source, ttl_millis 0 keeps it until it's deleted. flags is a combination
+* of CACHE_SHARED and CACHE_PERSISTENT, the write replaces the previous flags and source
+* becomes the owner.
+*/
+pub async fn write_entry(source: &str, channel: &str, key: &str, ttl_millis: u32, flags: u8, payload: Bytes) -> Result<(), PacketError> {
+    let key = make_key(channel, key)?;
+    let now = Utc::now();
+    let mut store = CACHE.lock().await;
+
+    if let Some(previous) = store.get(&key, now) {
+        check_owner(previous, source)?;
+    }
+    store.remove(&key)?;
+
+    let entry = CacheEntry {
+        payload,
+        owner: source.into(),
+        shared: flags & CACHE_SHARED != 0,
+        persistent: flags & CACHE_PERSISTENT != 0,
+        expires_at: (ttl_millis > 0).then(|| now + TimeDelta::milliseconds(ttl_millis as i64)),
+        updated_at: now
+    };
+
+    if entry.persistent {
+        PERSISTED.insert(persisted_key(&key), encode_entry(&entry)).map_err(BackendError::from)?;
+    }
+    store.insert(key, entry);
+
+    Ok(())
+}
+
+pub async fn delete_entry(source: &str, channel: &str, key: &str) -> Result<(), PacketError> {
+    let key = make_key(channel, key)?;
+    let mut store = CACHE.lock().await;
+
+    if let Some(entry) = store.get(&key, Utc::now()) {
+        check_owner(entry, source)?;
+    }
+    store.remove(&key)?;
+
+    Ok(())
+}
+
+/**
+* Called when a client disconnects, drops its entries that are neither shared nor persistent.
+*/
+pub async fn drop_owned_entries(owner: &str) {
+    let mut store = CACHE.lock().await;
+    let owned: Vec<Key> = store.entries.iter()
+        .filter(|(_, entry)| entry.owner.as_ref() == owner && !entry.shared && !entry.persistent)
+        .map(|(key, _)| key.clone())
+        .collect();
+
+    for key in owned {
+        // Not persistent, nothing to remove from sled
+        let _ = store.remove(&key);
+    }
+}
+
+/**
+* Removes every entry that expired by now, returns how many were removed.
+*/
+pub async fn remove_expired_entries(now: DateTime<Utc>) -> Result<usize, BackendError> {
+    let mut store = CACHE.lock().await;
+    let expired: Vec<Key> = store.expiries.iter()
+        .take_while(|(millis, _)| *millis <= now.timestamp_millis())
+        .map(|(_, key)| key.clone())
+        .collect();
+
+    for key in &expired {
+        store.remove(key)?;
+    }
+    Ok(expired.len())
+}
+
+/**
+* Live entries, only those of channel if given, sorted by channel then key.
+*/
+pub async fn list_entries(channel: Option<&str>) -> Vec<(Key, CacheEntry)> {
+    let store = CACHE.lock().await;
+    let now = Utc::now();
+
+    store.entries.iter()
+        .filter(|((c, _), entry)| channel.is_none_or(|channel| c.as_ref() == channel) && !entry.is_expired(now))
+        .map(|(key, entry)| (key.clone(), entry.clone()))
+        .collect()
+}
+
+pub async fn get_entry(channel: &str, key: &str) -> Option<CacheEntry> {
+    CACHE.lock().await.get(&(channel.into(), key.into()), Utc::now()).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(shared: bool, persistent: bool, expires_at: Option<i64>) -> CacheEntry {
+        CacheEntry {
+            payload: Bytes::from_static(b"party data"),
+            owner: "lobby-1".into(),
+            shared, persistent,
+            expires_at: expires_at.and_then(DateTime::from_timestamp_millis),
+            updated_at: DateTime::from_timestamp_millis(1_700_000_000_000).unwrap()
+        }
+    }
+
+    fn assert_same(a: &CacheEntry, b: &CacheEntry) {
+        assert_eq!((&a.payload, &a.owner, a.shared, a.persistent, a.expires_at, a.updated_at), (&b.payload, &b.owner, b.shared, b.persistent, b.expires_at, b.updated_at));
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        for original in [entry(false, true, None), entry(true, true, Some(1_700_000_060_000)), entry(true, false, None)] {
+            assert_same(&decode_entry(&encode_entry(&original)).unwrap(), &original);
+        }
+
+        let mut empty = entry(false, true, None);
+        empty.payload = Bytes::new();
+        assert_same(&decode_entry(&encode_entry(&empty)).unwrap(), &empty);
+    }
+
+    #[test]
+    fn truncated_entries_are_rejected() {
+        let raw = encode_entry(&entry(true, true, Some(1_700_000_060_000)));
+
+        // Everything up to the end of the owner is required, the payload can be empty
+        for len in 0..19 + "lobby-1".len() {
+            assert!(decode_entry(&raw[..len]).is_none(), "truncated to {len}");
+        }
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        let key: Key = ("parties".into(), "leader:notch".into());
+
+        assert_eq!(decode_key(&persisted_key(&key)), Some(key));
+        assert_eq!(decode_key(b"parties.invites\0a\0b"), Some(("parties.invites".into(), "a\0b".into())));
+        assert_eq!(decode_key(b"no separator"), None);
+        assert_eq!(decode_key(&[b'a', 0, 0xff]), None);
+    }
+
+    #[test]
+    fn keys_are_validated() {
+        assert!(make_key("parties", "a").is_ok());
+        assert_eq!(make_key("parties", "").unwrap_err().code, ErrorCode::Malformed);
+        assert_eq!(make_key("parties", &"a".repeat(MAX_KEY_LENGTH + 1)).unwrap_err().code, ErrorCode::Malformed);
+        assert_eq!(make_key("parties.*", "a").unwrap_err().code, ErrorCode::InvalidChannel);
+    }
+}
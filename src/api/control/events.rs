@@ -0,0 +1,62 @@
+use std::sync::LazyLock;
+
+use json::{JsonValue, object};
+use tokio::sync::broadcast::{Receiver, Sender, channel};
+
+const EVENT_CAPACITY: usize = 1024;
+
+static EVENTS: LazyLock<Sender<Event>> = LazyLock::new(|| channel(EVENT_CAPACITY).0);
+
+/**
+* Invalidation events published by storage mutations, servers holding cached users or groups
+* use them to refresh their copies.
+*/
+#[derive(Clone)]
+pub enum Event {
+    GroupUpdated { group: Box<str> },
+    GroupDeleted { group: Box<str> },
+    DefaultGroupChanged { group: Box<str> },
+    UserGroupsChanged { uuid: Box<str> },
+    UserPermissionsChanged { uuid: Box<str> },
+    UserPunished { uuid: Box<str>, punishment_id: u64 },
+    PunishmentRevoked { uuid: Box<str>, punishment_id: u64 }
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::GroupUpdated { .. } => "group_updated",
+            Event::GroupDeleted { .. } => "group_deleted",
+            Event::DefaultGroupChanged { .. } => "default_group_changed",
+            Event::UserGroupsChanged { .. } => "user_groups_changed",
+            Event::UserPermissionsChanged { .. } => "user_permissions_changed",
+            Event::UserPunished { .. } => "user_punished",
+            Event::PunishmentRevoked { .. } => "punishment_revoked"
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut json = match self {
+            Event::GroupUpdated { group } | Event::GroupDeleted { group } | Event::DefaultGroupChanged { group } => object! { group: group.as_ref() },
+            Event::UserGroupsChanged { uuid } | Event::UserPermissionsChanged { uuid } => object! { uuid: uuid.as_ref() },
+            Event::UserPunished { uuid, punishment_id } | Event::PunishmentRevoked { uuid, punishment_id } => object! {
+                uuid: uuid.as_ref(),
+                punishment_id: *punishment_id
+            }
+        };
+
+        json["event"] = self.name().into();
+        json
+    }
+}
+
+/**
+* Publishes an event to every subscriber, events published while nobody listens are dropped.
+*/
+pub fn publish(event: Event) {
+    let _ = EVENTS.send(event);
+}
+
+pub fn subscribe() -> Receiver<Event> {
+    EVENTS.subscribe()
+}
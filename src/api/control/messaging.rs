@@ -0,0 +1,113 @@
+use chrono::Utc;
+
+use crate::api::typedef::{PmsMode, User, punishment::{Punishment, Restrictions}};
+
+pub const MAX_PRIVATE_MESSAGE_LENGTH: usize = 256;
+
+/**
+* Why a private message was refused.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PmBlock {
+    Muted,
+    Ignored,
+    PmsDisabled,
+    FriendsOnly,
+    DoNotDisturb
+}
+
+impl PmBlock {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Muted => "muted",
+            Self::Ignored => "ignored",
+            Self::PmsDisabled => "pms_disabled",
+            Self::FriendsOnly => "friends_only",
+            Self::DoNotDisturb => "dnd"
+        }
+    }
+}
+
+/**
+* Checks whether sender can message recipient. sender_punishments are the sender's effective
+* punishments, any of them denying chat blocks the message. The recipient's settings are
+* checked in order: ignore list, pms mode (friends only means friends of the recipient), then
+* do not disturb, which only matters when the recipient is online since offline recipients get
+* the message in their inbox.
+*/
+pub fn check_private_message(sender: &User, sender_punishments: &[Punishment], recipient: &User, online: bool) -> Result<(), PmBlock> {
+    if !Restrictions::evaluate(sender_punishments, Utc::now()).can_chat {
+        return Err(PmBlock::Muted);
+    }
+    if recipient.ignores.iter().any(|i| i.uuid == sender.uuid) {
+        return Err(PmBlock::Ignored);
+    }
+
+    match recipient.pms {
+        PmsMode::PmsDisabled => return Err(PmBlock::PmsDisabled),
+        PmsMode::PmsEnabledFriendsOnly if !recipient.friends.iter().any(|f| f.uuid == sender.uuid) => return Err(PmBlock::FriendsOnly),
+        _ => {}
+    }
+
+    if online && recipient.dnd {
+        return Err(PmBlock::DoNotDisturb);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::typedef::{UserMapping, punishment::MUTE_TYPE};
+
+    use super::*;
+
+    fn mapping(uuid: &str) -> UserMapping {
+        UserMapping { uuid: uuid.into(), name: uuid.into() }
+    }
+
+    #[test]
+    fn allowed_by_default() {
+        assert_eq!(check_private_message(&User::new("a", "a"), &[], &User::new("b", "b"), true), Ok(()));
+    }
+
+    #[test]
+    fn muted_senders_are_blocked_first() {
+        let mut recipient = User::new("b", "b");
+        recipient.pms = PmsMode::PmsDisabled;
+        recipient.ignores.push(mapping("a"));
+
+        assert_eq!(check_private_message(&User::new("a", "a"), &[Punishment::test_default(1, MUTE_TYPE, Utc::now(), Some(1))], &recipient, true), Err(PmBlock::Muted));
+    }
+
+    #[test]
+    fn ignores_come_before_pms_mode() {
+        let mut recipient = User::new("b", "b");
+        recipient.pms = PmsMode::PmsDisabled;
+        recipient.ignores.push(mapping("a"));
+
+        assert_eq!(check_private_message(&User::new("a", "a"), &[], &recipient, true), Err(PmBlock::Ignored));
+        assert_eq!(check_private_message(&User::new("c", "c"), &[], &recipient, true), Err(PmBlock::PmsDisabled));
+    }
+
+    #[test]
+    fn friends_only() {
+        let mut recipient = User::new("b", "b");
+        recipient.pms = PmsMode::PmsEnabledFriendsOnly;
+        recipient.friends.push(mapping("a"));
+
+        assert_eq!(check_private_message(&User::new("a", "a"), &[], &recipient, true), Ok(()));
+        assert_eq!(check_private_message(&User::new("c", "c"), &[], &recipient, true), Err(PmBlock::FriendsOnly));
+    }
+
+    #[test]
+    fn dnd_only_applies_online() {
+        let mut recipient = User::new("b", "b");
+        recipient.dnd = true;
+
+        assert_eq!(check_private_message(&User::new("a", "a"), &[], &recipient, true), Err(PmBlock::DoNotDisturb));
+        assert_eq!(check_private_message(&User::new("a", "a"), &[], &recipient, false), Ok(()));
+
+        recipient.pms = PmsMode::PmsEnabledFriendsOnly;
+        assert_eq!(check_private_message(&User::new("a", "a"), &[], &recipient, true), Err(PmBlock::FriendsOnly));
+    }
+}
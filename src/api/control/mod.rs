@@ -2,4 +2,11 @@ pub mod storage;
 pub mod http;
 pub mod microsoft_lifecycle;
 pub mod inotify;
-pub mod ioutils;
+pub mod events;
+pub mod rpc;
+pub mod pubsub;
+pub mod outbound;
+pub mod registry;
+pub mod presence;
+pub mod messaging;
+pub mod cache;
@@ -0,0 +1,69 @@
+use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
+
+use json::{JsonValue, object};
+use tokio::sync::mpsc::{Receiver, Sender, channel, error::TrySendError};
+use tokio_util::sync::CancellationToken;
+use tungstenite::Message;
+
+use crate::api::typedef::{BackendError, fs_json::websocket::OverflowPolicy};
+
+#[derive(Default)]
+struct QueueMetrics {
+    sent: AtomicU64,
+    dropped: AtomicU64
+}
+
+/**
+* Bounded outbound queue of a websocket client, drained by its writer task. Sending never
+* waits: when the queue is full the message is dropped and, with the disconnect policy, the
+* client is kicked.
+*/
+#[derive(Clone)]
+pub struct OutboundQueue {
+    sender: Sender<Message>,
+    policy: OverflowPolicy,
+    kick: CancellationToken,
+    metrics: Arc<QueueMetrics>
+}
+
+impl OutboundQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy, kick: CancellationToken) -> (Self, Receiver<Message>) {
+        let (sender, receiver) = channel(capacity);
+
+        (Self { sender, policy, kick, metrics: Arc::default() }, receiver)
+    }
+
+    pub fn send(&self, msg: Message) -> Result<(), BackendError> {
+        match self.sender.try_send(msg) {
+            Ok(()) => {
+                self.metrics.sent.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            },
+            Err(TrySendError::Full(_)) => {
+                self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                if self.policy == OverflowPolicy::Disconnect {
+                    self.kick.cancel();
+                }
+                Err(BackendError::new("Outbound queue full", 503))
+            },
+            Err(TrySendError::Closed(_)) => Err(BackendError::new("Client disconnected", 500))
+        }
+    }
+
+    /**
+    * Messages waiting to be written to the socket.
+    */
+    pub fn depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    pub fn metrics_json(&self) -> JsonValue {
+        object! {
+            queue_depth: self.depth(),
+            queue_capacity: self.sender.max_capacity(),
+            sent: self.metrics.sent.load(Ordering::Relaxed),
+            dropped: self.metrics.dropped.load(Ordering::Relaxed),
+            policy: self.policy.as_str()
+        }
+    }
+}
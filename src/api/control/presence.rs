@@ -0,0 +1,78 @@
+use std::{collections::HashMap, sync::{Arc, LazyLock}};
+
+use chrono::{DateTime, Utc};
+use json::{JsonValue, object};
+use tokio::sync::Mutex;
+
+/**
+* Where an online player is, since is when they joined that server.
+*/
+#[derive(Clone)]
+pub struct Presence {
+    pub uuid: Box<str>,
+    pub name: Box<str>,
+    pub server: Box<str>,
+    pub since: DateTime<Utc>
+}
+
+type Players = HashMap<Box<str>, Presence>;
+
+/**
+* Online players by uuid, fed by the PLAYER_JOIN and PLAYER_QUIT packets of the connected
+* servers.
+*/
+static PLAYERS: LazyLock<Arc<Mutex<Players>>> = LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+impl Presence {
+    pub fn to_json(&self) -> JsonValue {
+        object! {
+            uuid: self.uuid.as_ref(),
+            name: self.name.as_ref(),
+            server: self.server.as_ref(),
+            since: self.since.timestamp_millis()
+        }
+    }
+}
+
+/**
+* A player joined server, moving between servers is a join on the new one.
+*/
+pub async fn player_joined(server: &str, uuid: &str, name: &str) {
+    PLAYERS.lock().await.insert(uuid.into(), Presence { uuid: uuid.into(), name: name.into(), server: server.into(), since: Utc::now() });
+}
+
+/**
+* A player left server, ignored if they already joined another one since the quit of the old
+* server can arrive after the join on the new one.
+*/
+pub async fn player_quit(server: &str, uuid: &str) {
+    let mut players = PLAYERS.lock().await;
+
+    if players.get(uuid).is_some_and(|p| p.server.as_ref() == server) {
+        players.remove(uuid);
+    }
+}
+
+/**
+* Called when a server disconnects, its players are considered offline.
+*/
+pub async fn clear_server(server: &str) {
+    PLAYERS.lock().await.retain(|_, p| p.server.as_ref() != server);
+}
+
+pub async fn find_player(uuid: &str) -> Option<Presence> {
+    PLAYERS.lock().await.get(uuid).cloned()
+}
+
+/**
+* The players of uuids that are online.
+*/
+pub async fn find_players<'a>(uuids: impl Iterator<Item = &'a str>) -> Vec<Presence> {
+    let players = PLAYERS.lock().await;
+
+    uuids.filter_map(|uuid| players.get(uuid).cloned()).collect()
+}
+
+pub async fn online_count() -> usize {
+    PLAYERS.lock().await.len()
+}
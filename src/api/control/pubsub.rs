@@ -0,0 +1,138 @@
+use std::{collections::{HashMap, HashSet}, sync::{Arc, LazyLock}};
+
+use tokio::sync::Mutex;
+
+use crate::api::typedef::protocol::{ErrorCode, PacketError};
+
+const MAX_SUBSCRIPTIONS: usize = 256;
+
+type Subscriptions = HashMap<Box<str>, HashSet<Box<str>>>;
+
+/**
+* Channel patterns each websocket client subscribed to, by client name.
+*/
+static SUBSCRIPTIONS: LazyLock<Arc<Mutex<Subscriptions>>> = LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/**
+* Channels are dot separated segments ("parties.invites"), a pattern may use "*" as a segment
+* to match any single segment, a trailing "*" matches one or more ("parties.*").
+*/
+pub fn validate_channel(channel: &str, pattern: bool) -> Result<(), PacketError> {
+    let valid = !channel.is_empty() && channel.split('.').all(|segment| {
+        !segment.is_empty() && ((pattern && segment == "*") || segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+    });
+
+    if !valid {
+        return Err(PacketError::new(ErrorCode::InvalidChannel, &format!("Invalid channel {channel}")));
+    }
+    Ok(())
+}
+
+pub fn channel_matches(pattern: &str, channel: &str) -> bool {
+    let mut pattern = pattern.split('.').peekable();
+    let mut channel = channel.split('.');
+
+    while let Some(expected) = pattern.next() {
+        let last = pattern.peek().is_none();
+
+        match (expected, channel.next()) {
+            (_, None) => return false,
+            ("*", Some(_)) if last => return true,
+            ("*", Some(_)) => {},
+            (expected, Some(segment)) if expected == segment => {},
+            _ => return false
+        }
+    }
+
+    channel.next().is_none()
+}
+
+pub async fn add_subscription(name: &str, pattern: &str) -> Result<(), PacketError> {
+    validate_channel(pattern, true)?;
+
+    let mut subscriptions = SUBSCRIPTIONS.lock().await;
+    let patterns = subscriptions.entry(name.into()).or_default();
+    if patterns.len() >= MAX_SUBSCRIPTIONS && !patterns.contains(pattern) {
+        return Err(PacketError::new(ErrorCode::InvalidChannel, "Too many subscriptions"));
+    }
+    patterns.insert(pattern.into());
+
+    Ok(())
+}
+
+pub async fn remove_subscription(name: &str, pattern: &str) {
+    let mut subscriptions = SUBSCRIPTIONS.lock().await;
+
+    if let Some(patterns) = subscriptions.get_mut(name) {
+        patterns.remove(pattern);
+        if patterns.is_empty() {
+            subscriptions.remove(name);
+        }
+    }
+}
+
+/**
+* Called when a client disconnects.
+*/
+pub async fn remove_subscriptions(name: &str) {
+    SUBSCRIPTIONS.lock().await.remove(name);
+}
+
+/**
+* Names of the clients subscribed to channel through any of their patterns.
+*/
+pub async fn get_subscribers(channel: &str) -> Vec<Box<str>> {
+    let subscriptions = SUBSCRIPTIONS.lock().await;
+
+    subscriptions.iter()
+        .filter(|(_, patterns)| patterns.iter().any(|p| channel_matches(p, channel)))
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_channels() {
+        assert!(validate_channel("parties.invites", false).is_ok());
+        assert!(validate_channel("games.bed_wars-2.start", false).is_ok());
+        assert!(validate_channel("parties.*", true).is_ok());
+        assert!(validate_channel("*", true).is_ok());
+    }
+
+    #[test]
+    fn invalid_channels() {
+        for channel in ["", ".", "parties.", ".parties", "parties..invites", "parties invites", "parties.in*"] {
+            assert_eq!(validate_channel(channel, true).unwrap_err().code, ErrorCode::InvalidChannel, "{channel}");
+        }
+        assert!(validate_channel("parties.*", false).is_err());
+    }
+
+    #[test]
+    fn exact_patterns() {
+        assert!(channel_matches("parties.invites", "parties.invites"));
+        assert!(!channel_matches("parties.invites", "parties.invites.sent"));
+        assert!(!channel_matches("parties.invites.sent", "parties.invites"));
+        assert!(!channel_matches("parties.invites", "parties.kicks"));
+    }
+
+    #[test]
+    fn single_segment_wildcards() {
+        assert!(channel_matches("games.*.start", "games.bedwars.start"));
+        assert!(!channel_matches("games.*.start", "games.bedwars.end"));
+        assert!(!channel_matches("games.*.start", "games.start"));
+        assert!(!channel_matches("games.*.start", "games.a.b.start"));
+    }
+
+    #[test]
+    fn trailing_wildcards() {
+        assert!(channel_matches("parties.*", "parties.invites"));
+        assert!(channel_matches("parties.*", "parties.a.b"));
+        assert!(!channel_matches("parties.*", "parties"));
+        assert!(!channel_matches("parties.*", "queues.ranked"));
+        assert!(channel_matches("*", "parties"));
+        assert!(channel_matches("*", "parties.invites"));
+    }
+}
@@ -0,0 +1,68 @@
+use std::{collections::HashMap, sync::{Arc, LazyLock}};
+
+use chrono::Utc;
+use json::JsonValue;
+use tokio::sync::Mutex;
+
+use crate::api::typedef::{jsonutils::SerializableJson, protocol::{ErrorCode, PacketError}, server::ServerInfo};
+
+type Servers = HashMap<Box<str>, ServerInfo>;
+
+/**
+* Servers registered through the core websocket, by client name. A server leaves the registry
+* when its session ends.
+*/
+static SERVERS: LazyLock<Arc<Mutex<Servers>>> = LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/**
+* Registers the server connected as name, metadata is the REGISTER_SERVER json. Registering
+* again updates the metadata and keeps the last status.
+*/
+pub async fn register_server(name: &str, mut metadata: JsonValue) -> Result<(), PacketError> {
+    metadata["name"] = name.into();
+
+    let mut info = ServerInfo::from_json(&metadata).map_err(|e| PacketError::new(ErrorCode::Malformed, e.get_msg()))?;
+    let mut servers = SERVERS.lock().await;
+
+    if let Some(previous) = servers.get(name) {
+        info.players = previous.players;
+        info.tps = previous.tps;
+        info.registered_at = previous.registered_at;
+    }
+    servers.insert(name.into(), info);
+
+    Ok(())
+}
+
+pub async fn update_server_status(name: &str, players: u32, tps: f32) -> Result<(), PacketError> {
+    let mut servers = SERVERS.lock().await;
+    let info = servers.get_mut(name).ok_or(PacketError::new(ErrorCode::NotRegistered, "Send REGISTER_SERVER before its status"))?;
+
+    info.players = players;
+    info.tps = tps;
+    info.updated_at = Utc::now();
+
+    Ok(())
+}
+
+pub async fn unregister_server(name: &str) {
+    SERVERS.lock().await.remove(name);
+}
+
+/**
+* Registered servers matching the optional type and region, sorted by name.
+*/
+pub async fn list_servers(r#type: Option<&str>, region: Option<&str>) -> Vec<ServerInfo> {
+    let servers = SERVERS.lock().await;
+    let mut list: Vec<ServerInfo> = servers.values()
+        .filter(|s| r#type.is_none_or(|t| s.r#type.as_ref() == t) && region.is_none_or(|r| s.region.as_ref() == r))
+        .cloned()
+        .collect();
+
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    list
+}
+
+pub async fn get_server(name: &str) -> Option<ServerInfo> {
+    SERVERS.lock().await.get(name).cloned()
+}
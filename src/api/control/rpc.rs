@@ -0,0 +1,100 @@
+use std::{collections::HashMap, sync::{Arc, LazyLock, atomic::{AtomicI64, Ordering}}, time::Duration};
+
+use hyper::body::Bytes;
+use tokio::{sync::Mutex, task::JoinHandle};
+use tungstenite::Message;
+
+use crate::api::{control::outbound::OutboundQueue, typedef::protocol::{RpcStatus, ServerPacket}};
+
+const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_RPC_TIMEOUT: Duration = Duration::from_secs(60);
+
+/**
+* A request forwarded to its target and waiting for the response, request_id is the id the
+* requester chose, the target only sees the backend's id.
+*/
+struct PendingRequest {
+    requester: Box<str>,
+    target: Box<str>,
+    request_id: i64,
+    sender: OutboundQueue,
+    timeout: JoinHandle<()>
+}
+
+static PENDING: LazyLock<Arc<Mutex<HashMap<i64, PendingRequest>>>> = LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+static NEXT_ID: AtomicI64 = AtomicI64::new(1);
+
+/**
+* Answers a request, failures to send are ignored since the requester might be gone.
+*/
+pub fn reply(sender: &OutboundQueue, request_id: i64, status: RpcStatus, payload: Bytes) {
+    let _ = sender.send(Message::Binary(ServerPacket::Response { request_id, status, payload }.encode()));
+}
+
+/**
+* Registers a request from requester to target and returns the id it's forwarded with. If no
+* response arrives within timeout_millis (0 for the default, capped to a minute) the requester
+* gets a TIMEOUT response.
+*/
+pub async fn open_request(requester: &str, request_id: i64, target: &str, timeout_millis: u32, sender: OutboundQueue) -> i64 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let timeout = match timeout_millis {
+        0 => DEFAULT_RPC_TIMEOUT,
+        millis => Duration::from_millis(millis as u64).min(MAX_RPC_TIMEOUT)
+    };
+
+    let pending = PENDING.clone();
+    let handle = tokio::spawn(async move {
+        tokio::time::sleep(timeout).await;
+
+        if let Some(request) = pending.lock().await.remove(&id) {
+            reply(&request.sender, request.request_id, RpcStatus::Timeout, Bytes::new());
+        }
+    });
+
+    PENDING.lock().await.insert(id, PendingRequest {
+        requester: requester.into(),
+        target: target.into(),
+        request_id,
+        sender,
+        timeout: handle
+    });
+
+    id
+}
+
+/**
+* Takes the request a response answers, only its target can answer it. Returns the requester's
+* sender and its request id, None if it already timed out or was never sent to responder.
+*/
+pub async fn close_request(id: i64, responder: &str) -> Option<(OutboundQueue, i64)> {
+    let mut pending = PENDING.lock().await;
+
+    if pending.get(&id).is_none_or(|r| r.target.as_ref() != responder) {
+        return None;
+    }
+    let request = pending.remove(&id)?;
+    request.timeout.abort();
+
+    Some((request.sender, request.request_id))
+}
+
+/**
+* Called when a client disconnects, requests waiting on it are answered with NOT_CONNECTED and
+* the ones it sent are dropped.
+*/
+pub async fn fail_requests(name: &str) {
+    let mut pending = PENDING.lock().await;
+
+    pending.retain(|_, request| {
+        let involved = request.target.as_ref() == name || request.requester.as_ref() == name;
+
+        if involved {
+            request.timeout.abort();
+            if request.requester.as_ref() != name {
+                reply(&request.sender, request.request_id, RpcStatus::NotConnected, Bytes::new());
+            }
+        }
+        !involved
+    });
+}
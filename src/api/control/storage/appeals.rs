@@ -0,0 +1,141 @@
+use std::{str::from_utf8, sync::{Arc, LazyLock}};
+
+use chrono::Utc;
+use json::stringify;
+use sled::Tree;
+
+use crate::api::typedef::{BackendError, appeal::{Appeal, AppealComment, AppealStatus}, jsonutils::SerializableJson, mailing::message::Message};
+use super::{query::{get_punishment, get_user_punishments, push_mail, revoke_punishment}, setup::get_client};
+
+static APPEALS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("appeals").expect("Failed to open 'appeals' tree")));
+// "{uuid}:" followed by the appeal id in big endian
+static USER_APPEALS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("uappeals").expect("Failed to open 'uappeals' tree")));
+
+fn user_key(uuid: &str, id: u64) -> Vec<u8> {
+    [format!("{uuid}:").as_bytes(), &id.to_be_bytes()].concat()
+}
+
+fn save_appeal(appeal: &Appeal) -> Result<(), BackendError> {
+    let tree = APPEALS.clone();
+
+    tree.insert(appeal.id.to_be_bytes(), stringify(appeal.to_json()).as_bytes())?;
+    Ok(())
+}
+
+pub fn get_appeal(id: u64) -> Result<Option<Appeal>, BackendError> {
+    let tree = APPEALS.clone();
+
+    match tree.get(id.to_be_bytes())? {
+        Some(raw) => Ok(Some(Appeal::from_json(&json::parse(from_utf8(&raw)?)?)?)),
+        None => Ok(None)
+    }
+}
+
+/**
+* Opens an appeal for one of the user's active punishments, only one pending appeal per
+* punishment is allowed.
+*/
+pub fn create_appeal(uuid: &str, punishment_id: u64, message: &str) -> Result<Appeal, BackendError> {
+    let punishment = get_user_punishments(uuid)?.into_iter()
+        .find(|p| p.id == punishment_id)
+        .ok_or(BackendError::new("Punishment not found", 404))?;
+
+    if !punishment.is_active(Utc::now()) {
+        return Err(BackendError::new("This punishment is no longer active", 400));
+    }
+    if get_user_appeals(uuid)?.iter().any(|a| a.punishment_id == punishment_id && a.status == AppealStatus::Pending) {
+        return Err(BackendError::new("There is already a pending appeal for this punishment", 409));
+    }
+
+    let appeal = Appeal {
+        id: get_client().generate_id()?,
+        uuid: uuid.into(), punishment_id,
+        message: message.into(),
+        status: AppealStatus::Pending,
+        created_at: Utc::now(),
+        comments: vec![],
+        handled_by: None, resolved_at: None, resolution: None
+    };
+
+    save_appeal(&appeal)?;
+    USER_APPEALS.insert(user_key(uuid, appeal.id), &appeal.id.to_be_bytes())?;
+
+    Ok(appeal)
+}
+
+pub fn get_user_appeals(uuid: &str) -> Result<Vec<Appeal>, BackendError> {
+    let tree = USER_APPEALS.clone();
+    let mut res = vec![];
+
+    for entry in tree.scan_prefix(format!("{uuid}:")) {
+        let raw: [u8; 8] = entry?.1.as_ref().try_into().map_err(|_| BackendError::new("Internal Error", 500))?;
+
+        if let Some(appeal) = get_appeal(u64::from_be_bytes(raw))? {
+            res.push(appeal);
+        }
+    }
+
+    Ok(res)
+}
+
+/**
+* Get appeals, newest first, optionally filtered by status.
+*/
+pub fn list_appeals(status: Option<AppealStatus>, limit: usize) -> Result<Vec<Appeal>, BackendError> {
+    let tree = APPEALS.clone();
+    let mut res = vec![];
+
+    for entry in tree.iter().rev() {
+        let (_, raw) = entry?;
+        let appeal = Appeal::from_json(&json::parse(from_utf8(&raw)?)?)?;
+
+        if status.is_none_or(|s| s == appeal.status) {
+            res.push(appeal);
+            if res.len() >= limit {
+                break;
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+pub fn comment_appeal(id: u64, author: &str, text: &str) -> Result<Appeal, BackendError> {
+    let mut appeal = get_appeal(id)?.ok_or(BackendError::new("Appeal not found", 404))?;
+
+    appeal.comments.push(AppealComment { author: author.into(), text: text.into(), date: Utc::now() });
+    save_appeal(&appeal)?;
+
+    Ok(appeal)
+}
+
+/**
+* Accepts or denies a pending appeal, accepting it revokes the punishment. The player is notified
+* through their inbox either way.
+*/
+pub fn resolve_appeal(id: u64, accept: bool, actor: &str, resolution: &str) -> Result<Appeal, BackendError> {
+    let mut appeal = get_appeal(id)?.ok_or(BackendError::new("Appeal not found", 404))?;
+    if appeal.status != AppealStatus::Pending {
+        return Err(BackendError::new("This appeal has already been resolved", 409));
+    }
+
+    // The punishment may have been revoked by staff since, the appeal is still accepted
+    if accept && !get_punishment(appeal.punishment_id)?.is_some_and(|p| p.is_revoked()) {
+        revoke_punishment(&appeal.uuid, appeal.punishment_id, Some(actor), &format!("Appeal #{} accepted: {resolution}", appeal.id))?;
+    }
+
+    appeal.status = if accept { AppealStatus::Accepted } else { AppealStatus::Denied };
+    appeal.handled_by = Some(actor.into());
+    appeal.resolved_at = Some(Utc::now());
+    appeal.resolution = Some(resolution.into());
+    save_appeal(&appeal)?;
+
+    let msg = if accept {
+        format!("Your appeal for punishment #{} has been accepted, the punishment has been lifted. {resolution}", appeal.punishment_id)
+    } else {
+        format!("Your appeal for punishment #{} has been denied. {resolution}", appeal.punishment_id)
+    };
+    push_mail(&appeal.uuid, &Message::new("Appeals", &msg))?;
+
+    Ok(appeal)
+}
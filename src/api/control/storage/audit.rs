@@ -0,0 +1,104 @@
+use std::{str::from_utf8, sync::{Arc, LazyLock}};
+
+use chrono::Utc;
+use json::stringify;
+use sled::Tree;
+
+use crate::api::typedef::{BackendError, audit::{AuditAction, AuditEntry}, jsonutils::SerializableJson};
+use super::setup::get_client;
+
+// Entries are keyed by a generated id, so iterating the tree yields them in chronological order
+static AUDIT: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("audit").expect("Failed to open 'audit' tree")));
+// "{target}#" + entry id (big endian), the entries of a player are a prefix scan
+static BY_TARGET: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("atarget").expect("Failed to open 'atarget' tree")));
+
+pub struct AuditFilter<'a> {
+    pub target: Option<&'a str>,
+    pub actor: Option<&'a str>,
+    pub action: Option<AuditAction>,
+    pub punishment_id: Option<u64>,
+    /// Only entries older than this id, used as a cursor
+    pub before: Option<u64>,
+    pub limit: usize
+}
+
+pub fn log_action(action: AuditAction, punishment_id: u64, target: &str, actor: Option<&str>, reason: &str) -> Result<AuditEntry, BackendError> {
+    let tree = AUDIT.clone();
+
+    let entry = AuditEntry {
+        id: get_client().generate_id()?,
+        action, punishment_id,
+        target: target.into(),
+        actor: actor.map(|a| a.into()),
+        reason: reason.into(),
+        timestamp: Utc::now()
+    };
+
+    tree.insert(entry.id.to_be_bytes(), stringify(entry.to_json()).as_bytes())?;
+    BY_TARGET.insert(target_key(target, entry.id), &[])?;
+    Ok(entry)
+}
+
+fn target_key(target: &str, id: u64) -> Vec<u8> {
+    [target.as_bytes(), b"#", &id.to_be_bytes()].concat()
+}
+
+/**
+* Parses an entry and adds it to res if it matches the filter, returns whether the limit is
+* reached.
+*/
+fn push_matching(res: &mut Vec<AuditEntry>, value: &[u8], filter: &AuditFilter) -> Result<bool, BackendError> {
+    let entry = AuditEntry::from_json(&json::parse(from_utf8(value)?)?)?;
+
+    if filter.target.is_some_and(|t| t != entry.target.as_ref())
+        || filter.actor.is_some_and(|a| Some(a) != entry.actor.as_deref())
+        || filter.action.is_some_and(|a| a != entry.action)
+        || filter.punishment_id.is_some_and(|id| id != entry.punishment_id) {
+        return Ok(false);
+    }
+
+    res.push(entry);
+    Ok(res.len() >= filter.limit)
+}
+
+/**
+* Get the audit entries matching the filter, newest first. Filtering by target only reads that
+* player's entries through the target index.
+*/
+pub fn query_audit(filter: &AuditFilter) -> Result<Vec<AuditEntry>, BackendError> {
+    let tree = AUDIT.clone();
+    let mut res = vec![];
+
+    if let Some(target) = filter.target {
+        let prefix = [target.as_bytes(), b"#"].concat();
+        let iter = match filter.before {
+            Some(before) => BY_TARGET.range(prefix.clone()..target_key(target, before)),
+            None => BY_TARGET.scan_prefix(&prefix)
+        };
+
+        for key in iter.rev() {
+            let (key, _) = key?;
+            let Some(value) = tree.get(&key[prefix.len()..])? else { continue };
+
+            if push_matching(&mut res, &value, filter)? {
+                break;
+            }
+        }
+        return Ok(res);
+    }
+
+    let iter = match filter.before {
+        Some(before) => tree.range(..before.to_be_bytes()),
+        None => tree.iter()
+    };
+
+    for entry in iter.rev() {
+        let (_, value) = entry?;
+
+        if push_matching(&mut res, &value, filter)? {
+            break;
+        }
+    }
+
+    Ok(res)
+}
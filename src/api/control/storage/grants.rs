@@ -0,0 +1,172 @@
+use std::{str::from_utf8, sync::{Arc, LazyLock}};
+
+use chrono::{DateTime, Utc};
+use sled::{Batch, Tree};
+
+use crate::api::{control::events::{Event, publish}, typedef::BackendError};
+use super::{query::USERS, setup::get_client};
+
+// Users tree key of the grant ("{uuid}:groups:{group}" or "{uuid}:permissions:{perm}") -> expiry millis
+static GRANT_EXPIRIES: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("gexpiry").expect("Failed to open 'gexpiry' tree")));
+// Expiry millis in big endian followed by the users tree key, ordered so expired grants are a range scan
+static EXPIRY_QUEUE: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("gqueue").expect("Failed to open 'gqueue' tree")));
+
+/**
+* What a timed grant gives to the user.
+*/
+#[derive(Clone, Copy, PartialEq)]
+pub enum GrantKind {
+    Group,
+    Permission
+}
+
+impl GrantKind {
+    fn segment(&self) -> &'static str {
+        match self {
+            GrantKind::Group => "groups",
+            GrantKind::Permission => "permissions"
+        }
+    }
+
+    fn from_segment(segment: &str) -> Option<Self> {
+        match segment {
+            "groups" => Some(GrantKind::Group),
+            "permissions" => Some(GrantKind::Permission),
+            _ => None
+        }
+    }
+}
+
+fn grant_key(uuid: &str, kind: GrantKind, name: &str) -> String {
+    format!("{uuid}:{}:{name}", kind.segment())
+}
+
+/**
+* Sets when a group membership or user permission expires, None makes it permanent.
+*/
+pub fn set_grant_expiry(uuid: &str, kind: GrantKind, name: &str, expires_at: Option<DateTime<Utc>>) -> Result<(), BackendError> {
+    schedule_expiry(&GRANT_EXPIRIES, &EXPIRY_QUEUE, &grant_key(uuid, kind, name), expires_at)
+}
+
+fn schedule_expiry(expiries: &Tree, queue: &Tree, key: &str, expires_at: Option<DateTime<Utc>>) -> Result<(), BackendError> {
+    let previous = match expires_at {
+        Some(date) => expiries.insert(key, &date.timestamp_millis().to_be_bytes())?,
+        None => expiries.remove(key)?
+    };
+    if let Some(previous) = previous {
+        queue.remove([previous.as_ref(), key.as_bytes()].concat())?;
+    }
+    if let Some(date) = expires_at {
+        queue.insert([&date.timestamp_millis().to_be_bytes(), key.as_bytes()].concat(), &[])?;
+    }
+
+    Ok(())
+}
+
+pub fn get_grant_expiry(uuid: &str, kind: GrantKind, name: &str) -> Result<Option<DateTime<Utc>>, BackendError> {
+    let expiries = GRANT_EXPIRIES.clone();
+
+    match expiries.get(grant_key(uuid, kind, name))? {
+        Some(raw) => {
+            let millis = i64::from_be_bytes(raw.as_ref().try_into().map_err(|_| BackendError::new("Corrupt grant expiry", 500))?);
+            Ok(DateTime::from_timestamp_millis(millis))
+        },
+        None => Ok(None)
+    }
+}
+
+/**
+* Removes every grant that expired by now from the user data, each one publishes the matching
+* user invalidation event. Returns how many were removed.
+*/
+pub fn remove_expired_grants(now: DateTime<Utc>) -> Result<usize, BackendError> {
+    let events = drain_expired(&GRANT_EXPIRIES, &EXPIRY_QUEUE, &USERS, now)?;
+    let expired = events.len();
+
+    for event in events {
+        publish(event);
+    }
+
+    Ok(expired)
+}
+
+/**
+* Removes the grants that expired by now and returns the event of each one.
+*/
+fn drain_expired(expiries: &Tree, queue: &Tree, users: &Tree, now: DateTime<Utc>) -> Result<Vec<Event>, BackendError> {
+    let mut batch = Batch::default();
+    let mut events = vec![];
+
+    for entry in queue.range(..(now.timestamp_millis() + 1).to_be_bytes()) {
+        let (queue_key, _) = entry?;
+        batch.remove(queue_key.clone());
+
+        let key = &queue_key[8..];
+        users.remove(key)?;
+        expiries.remove(key)?;
+
+        let mut parts = from_utf8(key)?.splitn(3, ':');
+        if let (Some(uuid), Some(kind)) = (parts.next(), parts.next().and_then(GrantKind::from_segment)) {
+            events.push(match kind {
+                GrantKind::Group => Event::UserGroupsChanged { uuid: uuid.into() },
+                GrantKind::Permission => Event::UserPermissionsChanged { uuid: uuid.into() }
+            });
+        }
+    }
+    queue.apply_batch(batch)?;
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    fn trees() -> (Tree, Tree, Tree) {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+
+        (db.open_tree("gexpiry").unwrap(), db.open_tree("gqueue").unwrap(), db.open_tree("users").unwrap())
+    }
+
+    fn grant(trees: &(Tree, Tree, Tree), key: &str, expires_at: Option<DateTime<Utc>>) {
+        trees.2.insert(key, key.as_bytes()).unwrap();
+        schedule_expiry(&trees.0, &trees.1, key, expires_at).unwrap();
+    }
+
+    #[test]
+    fn only_expired_grants_are_removed() {
+        let trees = trees();
+        let now = Utc::now();
+        grant(&trees, "a:groups:vip", Some(now - TimeDelta::minutes(1)));
+        grant(&trees, "a:permissions:fly", Some(now));
+        grant(&trees, "b:groups:vip", Some(now + TimeDelta::hours(1)));
+        grant(&trees, "b:permissions:fly", None);
+
+        let events: Vec<_> = drain_expired(&trees.0, &trees.1, &trees.2, now).unwrap().iter().map(|e| e.to_json().to_string()).collect();
+
+        assert_eq!(events, [
+            r#"{"uuid":"a","event":"user_groups_changed"}"#,
+            r#"{"uuid":"a","event":"user_permissions_changed"}"#
+        ]);
+        assert!(!trees.2.contains_key("a:groups:vip").unwrap() && !trees.2.contains_key("a:permissions:fly").unwrap());
+        assert!(trees.2.contains_key("b:groups:vip").unwrap() && trees.2.contains_key("b:permissions:fly").unwrap());
+        assert_eq!((trees.0.len(), trees.1.len()), (1, 1));
+        assert!(drain_expired(&trees.0, &trees.1, &trees.2, now).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rescheduling_replaces_the_expiry() {
+        let trees = trees();
+        let now = Utc::now();
+        grant(&trees, "a:groups:vip", Some(now - TimeDelta::minutes(1)));
+        grant(&trees, "a:groups:vip", Some(now + TimeDelta::hours(1)));
+        grant(&trees, "a:permissions:fly", Some(now - TimeDelta::minutes(1)));
+        grant(&trees, "a:permissions:fly", None);
+
+        assert!(drain_expired(&trees.0, &trees.1, &trees.2, now).unwrap().is_empty());
+        assert_eq!((trees.0.len(), trees.1.len()), (1, 1));
+        assert_eq!(drain_expired(&trees.0, &trees.1, &trees.2, now + TimeDelta::hours(2)).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,162 @@
+use std::{net::{IpAddr, Ipv4Addr}, str::{FromStr, from_utf8}};
+
+use chrono::Utc;
+use json::stringify;
+use sled::Batch;
+
+use crate::api::typedef::{BackendError, jsonutils::SerializableJson, network::{Subnet, parse_address}, punishment::Punishment};
+use crate::api::encoder::encode_datetime;
+use super::punishment_index::rebuild_punishment_indexes;
+use super::query::{IP_HISTORY, IP_INDEXES, IP_PREFIXES, IP_PUNISHMENTS, PUNISHMENTS, USERS};
+use super::setup::get_client;
+
+/**
+* Runs every migration between the stored database version and the current one, in order.
+*/
+pub fn migrate(from: u8) -> Result<(), BackendError> {
+    if from < 1 {
+        migrate_ip_subnets()?;
+    }
+    if from < 2 {
+        migrate_ip_history()?;
+    }
+    if from < 3 {
+        rebuild_punishment_indexes()?;
+    }
+    if from < 4 {
+        migrate_user_groups()?;
+    }
+    if from < 5 {
+        migrate_mail_ids()?;
+    }
+
+    Ok(())
+}
+
+/**
+* v0 -> v1: ip punishments used to be keyed by the address truncated at its last '.', which is
+* an implicit IPv4 /24. They are rekeyed by CIDR ("1.2.3.0/24:{id}"), the punishment records
+* the subnet it applies to and /24 is added to the prefixes in use. Users also get their last
+* address from the ip index.
+*/
+fn migrate_ip_subnets() -> Result<(), BackendError> {
+    let tree = IP_PUNISHMENTS.clone();
+    let puns = PUNISHMENTS.clone();
+    let mut batch = Batch::default();
+
+    for entry in tree.iter() {
+        let (key, value) = entry?;
+        let key_str = from_utf8(&key)?;
+        batch.remove(key.clone());
+
+        let Some((truncated, id)) = key_str.rsplit_once(':') else { continue };
+        let Ok(network) = Ipv4Addr::from_str(&format!("{truncated}.0")) else {
+            println!("[migrations] Dropping malformed ip punishment entry {key_str}");
+            continue;
+        };
+        let subnet = Subnet::new(IpAddr::V4(network), 24)?;
+
+        batch.insert(format!("{subnet}:{id}").as_bytes(), value.clone());
+        IP_PREFIXES.insert(subnet.prefix_key(), &[])?;
+
+        if let Some(raw) = puns.get(&value)? {
+            let mut pun = Punishment::from_json(&json::parse(from_utf8(&raw)?)?)?;
+
+            pun.subnet = Some(subnet);
+            puns.insert(&value, stringify(pun.to_json()).as_bytes())?;
+        }
+    }
+    tree.apply_batch(batch)?;
+
+    let users = USERS.clone();
+    for entry in IP_INDEXES.iter() {
+        let (address, uuid) = entry?;
+
+        if let Ok(ip) = parse_address(from_utf8(&address)?) {
+            users.insert(format!("{}:address", from_utf8(&uuid)?), ip.to_string().as_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+/**
+* v1 -> v2: the ip index mapped an address to the last uuid seen with it. It becomes a
+* many-to-many "{ip}#{uuid}" index with its "{uuid}#{ip}" counterpart, the original first
+* seen date is unknown so the migration date is used for both timestamps.
+*/
+fn migrate_ip_history() -> Result<(), BackendError> {
+    let tree = IP_INDEXES.clone();
+    let history = IP_HISTORY.clone();
+    let now = encode_datetime(Utc::now());
+    let seen = [now, now].concat();
+    let mut batch = Batch::default();
+
+    for entry in tree.iter() {
+        let (key, uuid) = entry?;
+        let key_str = from_utf8(&key)?;
+        if key_str.contains('#') {
+            continue;
+        }
+        batch.remove(key.clone());
+
+        let (Ok(ip), Ok(uuid)) = (parse_address(key_str), from_utf8(&uuid)) else {
+            println!("[migrations] Dropping malformed ip index entry {key_str}");
+            continue;
+        };
+
+        batch.insert(format!("{ip}#{uuid}").as_bytes(), seen.as_slice());
+        history.insert(format!("{uuid}#{ip}"), seen.as_slice())?;
+    }
+    tree.apply_batch(batch)?;
+
+    Ok(())
+}
+
+/**
+* v3 -> v4: users could only hold one group ("{uuid}:group"), memberships are now keyed by
+* "{uuid}:groups:{group}".
+*/
+fn migrate_user_groups() -> Result<(), BackendError> {
+    let tree = USERS.clone();
+    let mut batch = Batch::default();
+
+    for entry in tree.iter() {
+        let (key, group) = entry?;
+        let Some(uuid) = from_utf8(&key)?.strip_suffix(":group") else { continue };
+
+        batch.remove(key.clone());
+        batch.insert(format!("{uuid}:groups:{}", from_utf8(&group)?).as_bytes(), group);
+    }
+    tree.apply_batch(batch)?;
+
+    Ok(())
+}
+
+/**
+* v4 -> v5: mails had no id, servers saved the whole inbox back. Every stored mail gets one so
+* they can be updated through update_mail.
+*/
+fn migrate_mail_ids() -> Result<(), BackendError> {
+    let tree = USERS.clone();
+    let client = get_client();
+    let mut batch = Batch::default();
+
+    for entry in tree.iter() {
+        let (key, raw) = entry?;
+        if !key.ends_with(b":mails") {
+            continue;
+        }
+
+        let mut inbox = json::parse(from_utf8(&raw)?)?;
+        for mail in inbox.members_mut() {
+            if mail["id"].is_null() {
+                mail["id"] = client.generate_id()?.into();
+            }
+        }
+        batch.insert(key, stringify(inbox).as_bytes());
+    }
+    tree.apply_batch(batch)?;
+
+    Ok(())
+}
@@ -1,2 +1,9 @@
 pub mod query;
 pub mod setup;
+pub mod migrations;
+pub mod audit;
+pub mod appeals;
+pub mod punishment_index;
+pub mod reports;
+pub mod notes;
+pub mod grants;
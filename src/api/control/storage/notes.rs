@@ -0,0 +1,85 @@
+use std::{str::from_utf8, sync::{Arc, LazyLock}};
+
+use chrono::{DateTime, Utc};
+use json::{JsonValue, object, stringify};
+use sled::Tree;
+
+use crate::api::typedef::{BackendError, jsonutils::SerializableJson, note::Note};
+use super::{query::{get_name_history, get_user_punishments}, reports::get_reports_against, setup::get_client};
+
+// "{uuid}:" followed by the note id in big endian
+static NOTES: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("notes").expect("Failed to open 'notes' tree")));
+
+fn note_key(uuid: &str, id: u64) -> Vec<u8> {
+    [format!("{uuid}:").as_bytes(), &id.to_be_bytes()].concat()
+}
+
+pub fn add_note(uuid: &str, author: &str, text: &str) -> Result<Note, BackendError> {
+    let tree = NOTES.clone();
+    let note = Note {
+        id: get_client().generate_id()?,
+        uuid: uuid.into(),
+        author: author.into(),
+        text: text.into(),
+        created_at: Utc::now()
+    };
+
+    tree.insert(note_key(uuid, note.id), stringify(note.to_json()).as_bytes())?;
+    Ok(note)
+}
+
+/**
+* Notes of the user, oldest first.
+*/
+pub fn get_notes(uuid: &str) -> Result<Vec<Note>, BackendError> {
+    let tree = NOTES.clone();
+    let mut res = vec![];
+
+    for entry in tree.scan_prefix(format!("{uuid}:")) {
+        let (_, raw) = entry?;
+        res.push(Note::from_json(&json::parse(from_utf8(&raw)?)?)?);
+    }
+
+    Ok(res)
+}
+
+pub fn delete_note(uuid: &str, id: u64) -> Result<(), BackendError> {
+    let tree = NOTES.clone();
+
+    if tree.remove(note_key(uuid, id))?.is_none() {
+        return Err(BackendError::new("Note not found", 404));
+    }
+    Ok(())
+}
+
+/**
+* Everything moderation related that happened to the user in chronological order: notes,
+* punishments (and their revocations), reports against the user and name changes.
+* Every entry is { type, date, data } where date is in millis.
+*/
+pub fn get_moderation_timeline(uuid: &str) -> Result<Vec<JsonValue>, BackendError> {
+    let mut entries: Vec<(DateTime<Utc>, JsonValue)> = vec![];
+    let mut push = |r#type: &str, date: DateTime<Utc>, data: JsonValue| {
+        entries.push((date, object! { type: r#type, date: date.timestamp_millis(), data: data }));
+    };
+
+    for note in get_notes(uuid)? {
+        push("note", note.created_at, note.to_json());
+    }
+    for pun in get_user_punishments(uuid)? {
+        if let Some(revoked_at) = pun.revoked_at {
+            push("revocation", revoked_at, pun.to_json());
+        }
+        push("punishment", pun.creation_date, pun.to_json());
+    }
+    for report in get_reports_against(uuid)? {
+        push("report", report.created_at, report.to_json());
+    }
+    for (name, date) in get_name_history(uuid)? {
+        push("name", date, object! { name: name.as_ref() });
+    }
+
+    entries.sort_by_key(|(date, _)| *date);
+
+    Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+}
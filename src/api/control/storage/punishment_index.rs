@@ -0,0 +1,240 @@
+use std::{ops::Range, str::from_utf8, sync::{Arc, LazyLock}};
+
+use chrono::Utc;
+use sled::{Batch, Tree};
+
+use crate::api::typedef::{BackendError, punishment::Punishment};
+use super::{query::{PUNISHMENTS, USERS, get_punishment}, setup::get_client};
+
+// Secondary indexes over the punishments tree. Keys end with the creation date in millis and the
+// punishment id (both big endian) so every index iterates in chronological order.
+static BY_DATE: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("pdate").expect("Failed to open 'pdate' tree")));
+// "{type}\0" + date + id
+static BY_TYPE: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("ptype").expect("Failed to open 'ptype' tree")));
+// Punishments that weren't revoked and hadn't expired when last seen, pruned lazily on reads
+static ACTIVE: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("pactive").expect("Failed to open 'pactive' tree")));
+// Punishment id -> uuid of the punished user
+static OWNERS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("powner").expect("Failed to open 'powner' tree")));
+
+pub struct PunishmentFilter<'a> {
+    pub r#type: Option<&'a str>,
+    pub active: Option<bool>,
+    /// Case insensitive substring of the reason
+    pub reason: Option<&'a str>,
+    pub issuer: Option<&'a str>,
+    /// Only punishments older than this cursor, as returned by a previous search
+    pub cursor: Option<&'a str>,
+    pub limit: usize
+}
+
+/**
+* Punishments with the uuid of their owner, and the cursor of the next page.
+*/
+pub type SearchPage = (Vec<(Punishment, Option<Box<str>>)>, Option<Box<str>>);
+
+fn date_key(pun: &Punishment) -> [u8; 16] {
+    let mut key = [0u8; 16];
+
+    key[0..8].copy_from_slice(&(pun.creation_date.timestamp_millis().max(0) as u64).to_be_bytes());
+    key[8..16].copy_from_slice(&pun.id.to_be_bytes());
+    key
+}
+
+fn type_prefix(r#type: &str) -> Vec<u8> {
+    [r#type.to_lowercase().as_bytes(), &[0]].concat()
+}
+
+fn encode_cursor(key: &[u8]) -> Box<str> {
+    let date = u64::from_be_bytes(key[0..8].try_into().unwrap());
+    let id = u64::from_be_bytes(key[8..16].try_into().unwrap());
+
+    format!("{date}-{id}").into()
+}
+
+fn decode_cursor(cursor: &str) -> Result<[u8; 16], BackendError> {
+    let (date, id) = cursor.split_once('-').ok_or(BackendError::new("Invalid cursor", 400))?;
+    let mut key = [0u8; 16];
+
+    key[0..8].copy_from_slice(&date.parse::<u64>().map_err(|_| BackendError::new("Invalid cursor", 400))?.to_be_bytes());
+    key[8..16].copy_from_slice(&id.parse::<u64>().map_err(|_| BackendError::new("Invalid cursor", 400))?.to_be_bytes());
+    Ok(key)
+}
+
+/**
+* The keys of an index under prefix that come before the cursor, which is the key of the last
+* entry of the previous page and so isn't part of the range.
+*/
+fn page_range(prefix: &[u8], cursor: Option<&str>) -> Result<Range<Vec<u8>>, BackendError> {
+    let end = match cursor {
+        Some(cursor) => [prefix, &decode_cursor(cursor)?].concat(),
+        None => [prefix, &[0xff; 16]].concat()
+    };
+
+    Ok(prefix.to_vec()..end)
+}
+
+/**
+* Adds the punishment to every index, call it again after it's modified.
+*/
+pub fn index_punishment(pun: &Punishment, owner: &str) -> Result<(), BackendError> {
+    let key = date_key(pun);
+
+    BY_DATE.insert(key, &[])?;
+    BY_TYPE.insert([type_prefix(&pun.r#type).as_slice(), &key].concat(), &[])?;
+    OWNERS.insert(pun.id.to_be_bytes(), owner.as_bytes())?;
+
+    if pun.is_active(Utc::now()) {
+        ACTIVE.insert(key, &[])?;
+    } else {
+        ACTIVE.remove(key)?;
+    }
+
+    Ok(())
+}
+
+pub fn get_punishment_owner(id: u64) -> Result<Option<Box<str>>, BackendError> {
+    match OWNERS.get(id.to_be_bytes())? {
+        Some(uuid) => Ok(Some(from_utf8(&uuid)?.into())),
+        None => Ok(None)
+    }
+}
+
+/**
+* Search punishments newest first. The most selective index available drives the iteration, the
+* rest of the filters are checked against the punishment itself. Returns the matches along with
+* their owner and the cursor for the next page if there may be one.
+*/
+pub fn search_punishments(filter: &PunishmentFilter) -> Result<SearchPage, BackendError> {
+    let now = Utc::now();
+    let (tree, prefix) = match (filter.r#type, filter.active) {
+        (Some(t), _) => (BY_TYPE.clone(), type_prefix(t)),
+        (None, Some(true)) => (ACTIVE.clone(), vec![]),
+        _ => (BY_DATE.clone(), vec![])
+    };
+
+    let mut res = vec![];
+    let mut last_key = None;
+    let mut stale = Batch::default();
+
+    for entry in tree.range(page_range(&prefix, filter.cursor)?).rev() {
+        let (key, _) = entry?;
+        let key = &key[prefix.len()..];
+        let id = u64::from_be_bytes(key[8..16].try_into().map_err(|_| BackendError::new("Internal Error", 500))?);
+        let Some(pun) = get_punishment(id)? else { continue };
+        let active = pun.is_active(now);
+
+        if filter.active == Some(true) && filter.r#type.is_none() && !active {
+            stale.remove(key);
+            continue;
+        }
+        if filter.active.is_some_and(|a| a != active)
+            || filter.issuer.is_some_and(|i| Some(i) != pun.issuer.as_deref())
+            || filter.reason.is_some_and(|r| !pun.reason.to_lowercase().contains(&r.to_lowercase())) {
+            continue;
+        }
+
+        last_key = Some(encode_cursor(key));
+        res.push((pun, get_punishment_owner(id)?));
+        if res.len() >= filter.limit {
+            break;
+        }
+    }
+    ACTIVE.apply_batch(stale)?;
+
+    let next = if res.len() >= filter.limit { last_key } else { None };
+    Ok((res, next))
+}
+
+/**
+* Rebuilds every index from the punishments tree, owners are found through the users tree.
+*/
+pub fn rebuild_punishment_indexes() -> Result<(), BackendError> {
+    for tree in [&BY_DATE, &BY_TYPE, &ACTIVE, &OWNERS] {
+        tree.clear()?;
+    }
+
+    for entry in USERS.iter() {
+        let (key, value) = entry?;
+        let key = from_utf8(&key)?;
+
+        if let Some((uuid, _)) = key.split_once(":punishments:") {
+            let raw: [u8; 8] = value.as_ref().try_into().map_err(|_| BackendError::new("Internal Error", 500))?;
+            if let Some(pun) = get_punishment(u64::from_be_bytes(raw))? {
+                index_punishment(&pun, uuid)?;
+            }
+        }
+    }
+
+    // Punishments without an owner still get indexed by date and type
+    for entry in PUNISHMENTS.iter() {
+        let (key, _) = entry?;
+        let raw: [u8; 8] = key.as_ref().try_into().map_err(|_| BackendError::new("Internal Error", 500))?;
+        let id = u64::from_be_bytes(raw);
+
+        if !OWNERS.contains_key(raw)? && let Some(pun) = get_punishment(id)? {
+            let key = date_key(&pun);
+            BY_DATE.insert(key, &[])?;
+            BY_TYPE.insert([type_prefix(&pun.r#type).as_slice(), &key].concat(), &[])?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeDelta};
+
+    use super::*;
+
+    fn key_at(r#type: &str, id: u64, minutes_ago: i64) -> Vec<u8> {
+        let mut pun = Punishment::test_default(id, r#type, Utc::now(), None);
+        pun.creation_date -= TimeDelta::minutes(minutes_ago);
+
+        [type_prefix(r#type).as_slice(), &date_key(&pun)].concat()
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let mut pun = Punishment::test_default(42, "ban", Utc::now(), None);
+        pun.creation_date = DateTime::from_timestamp_millis(1_000).unwrap();
+        let key = date_key(&pun);
+
+        assert_eq!(encode_cursor(&key).as_ref(), "1000-42");
+        assert_eq!(decode_cursor("1000-42").unwrap(), key);
+    }
+
+    #[test]
+    fn invalid_cursors() {
+        for invalid in ["", "12", "a-1", "1-b", "-1-2", "1-2-3"] {
+            assert!(decode_cursor(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn pages_continue_after_the_cursor() {
+        let prefix = type_prefix("ban");
+        let (newest, last, older) = (key_at("ban", 3, 0), key_at("ban", 2, 5), key_at("ban", 1, 10));
+
+        let first = page_range(&prefix, None).unwrap();
+        assert!([&newest, &last, &older].into_iter().all(|k| first.contains(k)));
+        assert!(!first.contains(&key_at("kick", 4, 0)));
+
+        let cursor = encode_cursor(&last[prefix.len()..]);
+        let next = page_range(&prefix, Some(&cursor)).unwrap();
+        assert!(next.contains(&older));
+        assert!(!next.contains(&last) && !next.contains(&newest));
+    }
+
+    #[test]
+    fn ids_break_date_ties() {
+        let mut pun = Punishment::test_default(7, "ban", Utc::now(), None);
+        let later = date_key(&pun);
+        pun.id = 6;
+        let earlier = date_key(&pun);
+
+        let next = page_range(&[], Some(&encode_cursor(&later))).unwrap();
+        assert!(next.contains(&earlier.to_vec()));
+        assert!(!next.contains(&later.to_vec()));
+    }
+}
@@ -1,19 +1,23 @@
-use std::{collections::HashSet, str::from_utf8, sync::{Arc, LazyLock}};
+use std::{cmp::Reverse, collections::HashSet, net::IpAddr, str::from_utf8, sync::{Arc, LazyLock}};
 
 use chrono::{DateTime, Utc};
-use json::stringify;
+use json::{JsonValue, stringify};
 use sled::{Batch, IVec, Tree, transaction::ConflictableTransactionError};
 
-use crate::api::{encoder::{decode_datetime, encode_datetime}, typedef::{BackendError, User, UserMapping, jsonutils::SerializableJson, mailing::{Mail, get_json_from_mails, get_mails_from_json}, permissions::{Group, Permission}, punishment::Punishment}};
-use super::setup::get_client;
+use crate::api::{control::events::{Event, publish}, encoder::{decode_datetime, encode_datetime}, typedef::{AltAccount, BackendError, audit::AuditAction, User, UserMapping, jsonutils::SerializableJson, network::{Subnet, family, parse_address}, mailing::{Mail, get_mails_from_json}, permissions::{Contexts, Group, Membership, Permission, PermissionHolder, parse_scoped_key, scoped_key}, punishment::Punishment}};
+use super::{audit::log_action, grants::{GrantKind, get_grant_expiry, set_grant_expiry}, punishment_index::index_punishment, setup::get_client};
 
 // Trees
-static USERS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("users").expect("Failed to open 'users' tree")));
-static NAME_INDEXES: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("nindex").expect("Failed to open 'nindex' tree")));
-static IP_INDEXES: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("iindex").expect("Failed to open 'iindex' tree")));
-static GROUPS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("groups").expect("Failed to open 'groups' tree")));
-static PUNISHMENTS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("punishments").expect("Failed to open 'punishments' tree")));
-static IP_PUNISHMENTS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("ip_punishments").expect("Failed to open 'ip_punishments' tree")));
+pub(super) static USERS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("users").expect("Failed to open 'users' tree")));
+pub(super) static NAME_INDEXES: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("nindex").expect("Failed to open 'nindex' tree")));
+pub(super) static NAME_HISTORY: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("nhistory").expect("Failed to open 'nhistory' tree")));
+pub(super) static IP_INDEXES: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("iindex").expect("Failed to open 'iindex' tree")));
+pub(super) static IP_HISTORY: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("ihistory").expect("Failed to open 'ihistory' tree")));
+pub(super) static GROUPS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("groups").expect("Failed to open 'groups' tree")));
+pub(super) static PUNISHMENTS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("punishments").expect("Failed to open 'punishments' tree")));
+pub(super) static IP_PUNISHMENTS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("ip_punishments").expect("Failed to open 'ip_punishments' tree")));
+// Subnet::prefix_key of every prefix length ip punishments were issued with, lookups only try those
+pub(super) static IP_PREFIXES: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("iprefix").expect("Failed to open 'iprefix' tree")));
 
 pub fn user_exists(uuid: &str) -> Result<bool, BackendError> {
     let tree = USERS.clone();
@@ -33,36 +37,91 @@ pub fn group_exists(name: &str) -> Result<bool, BackendError> {
     Ok(tree.get(format!("{name}:prefix"))?.is_some())
 }
 
-pub fn set_group_to_user(uuid: &str, group_name: &str) -> Result<(), BackendError> {
+/**
+* Replaces every group the user holds with the given one. Memberships are keyed by
+* "{uuid}:groups:{group}" or "{uuid}:groups:{group}@{contexts}" when scoped, expires_at makes
+* the membership temporary.
+*/
+pub fn set_group_to_user(uuid: &str, group_name: &str, contexts: &Contexts, expires_at: Option<DateTime<Utc>>) -> Result<(), BackendError> {
     if !user_exists(uuid)? || !group_exists(group_name)? {
         return Err(BackendError::new("user or group doesn't exist", 404));
     }
 
     let tree = USERS.clone();
-    tree.insert(format!("{uuid}:group"), group_name.as_bytes())?;
+    let prefix = format!("{uuid}:groups:");
+    let key = scoped_key(group_name, contexts);
+    let mut batch = Batch::default();
+    for old in tree.scan_prefix(&prefix).keys() {
+        let old = old?;
+        set_grant_expiry(uuid, GrantKind::Group, from_utf8(&old[prefix.len()..])?, None)?;
+        batch.remove(old);
+    }
+    batch.insert(format!("{prefix}{key}").as_bytes(), group_name.as_bytes());
+    tree.apply_batch(batch)?;
+    set_grant_expiry(uuid, GrantKind::Group, &key, expires_at)?;
+    publish(Event::UserGroupsChanged { uuid: uuid.into() });
 
     Ok(())
 }
 
-pub fn set_group_to_user_by_name(username: &str, group_name: &str) -> Result<(), BackendError> {
+pub fn set_group_to_user_by_name(username: &str, group_name: &str, contexts: &Contexts, expires_at: Option<DateTime<Utc>>) -> Result<(), BackendError> {
     let indexes = NAME_INDEXES.clone();
-    let uuid = indexes.get(username.as_bytes())?;
-    if !uuid.is_none() || !group_exists(group_name)? {
+    let uuid = indexes.get(username.as_bytes())?.ok_or(BackendError::new("user or group doesn't exist", 404))?;
+
+    set_group_to_user(from_utf8(&uuid)?, group_name, contexts, expires_at)
+}
+
+/**
+* Adds a group to the ones the user already holds, if the user already has it in the same
+* contexts only the expiry is updated.
+*/
+pub fn add_group_to_user(uuid: &str, group_name: &str, contexts: &Contexts, expires_at: Option<DateTime<Utc>>) -> Result<(), BackendError> {
+    if !user_exists(uuid)? || !group_exists(group_name)? {
         return Err(BackendError::new("user or group doesn't exist", 404));
     }
-    let uuid = uuid.unwrap();
 
     let tree = USERS.clone();
-    tree.insert(format!("{}:group", from_utf8(&uuid)?), group_name.as_bytes())?;
+    let key = scoped_key(group_name, contexts);
+    tree.insert(format!("{uuid}:groups:{key}"), group_name.as_bytes())?;
+    set_grant_expiry(uuid, GrantKind::Group, &key, expires_at)?;
+    publish(Event::UserGroupsChanged { uuid: uuid.into() });
 
     Ok(())
 }
 
+pub fn remove_group_from_user(uuid: &str, group_name: &str, contexts: &Contexts) -> Result<(), BackendError> {
+    let tree = USERS.clone();
+    let key = scoped_key(group_name, contexts);
+
+    if tree.remove(format!("{uuid}:groups:{key}"))?.is_none() {
+        return Err(BackendError::new("The user doesn't have that group", 404));
+    }
+    set_grant_expiry(uuid, GrantKind::Group, &key, None)?;
+    publish(Event::UserGroupsChanged { uuid: uuid.into() });
+
+    Ok(())
+}
+
+/**
+* Saves the user, permissions and group memberships aren't written here since servers save
+* cached users, they are managed with put_permission_to_user, delete_permission_from_user,
+* set_group_to_user, add_group_to_user and remove_group_from_user. The inbox isn't written
+* either, mails go through push_mail and update_mail.
+*/
 pub fn put_user(user: &User) -> Result<(), BackendError> {
     let tree = USERS.clone();
+    let pun_tree = PUNISHMENTS.clone();
 
-    tree.transaction(move |tree| {
-        let user = user;
+    // Stored punishments are owned by the backend (revocations, ip matches from other
+    // accounts), only unknown ones are taken from the user
+    let mut new_puns = vec![];
+    for pun in &user.punishments {
+        if !pun_tree.contains_key(pun.id.to_be_bytes())? {
+            new_puns.push(pun);
+        }
+    }
+
+    tree.transaction(|tree| {
         let uuid = user.uuid.as_ref();
 
         tree.insert(format!("{uuid}:name").as_bytes(), &*user.name)?;
@@ -79,24 +138,21 @@ pub fn put_user(user: &User) -> Result<(), BackendError> {
             tree.insert(format!("{uuid}:friends:{}", friend.uuid).as_bytes(), friend.uuid.as_bytes())?;
         }
 
-        let pun_tree = PUNISHMENTS.clone();
-        for pun in &user.punishments {
+        for pun in &new_puns {
             tree.insert(format!("{uuid}:punishments:{}", pun.id).as_bytes(), &pun.id.to_be_bytes())?;
             pun_tree.insert(pun.id.to_be_bytes(), stringify(pun.to_json()).as_bytes())?;
         }
-        for perm in &user.perms {
-            tree.insert(format!("{uuid}:permissions:{}", perm.perm).as_bytes(), &[perm.value as u8])?;
-        }
-        if let Some(group) = &user.group {
-            tree.insert(format!("{uuid}:group").as_bytes(), group.name.as_bytes())?;
-        }
-        tree.insert(format!("{uuid}:mails").as_bytes(), stringify(get_json_from_mails(&user.inbox)).as_bytes())?;
         for ignored in &user.ignores {
             tree.insert(format!("{uuid}:ignores:{}", ignored.uuid).as_bytes(), ignored.uuid.as_bytes())?;
         }
 
         Ok::<(), ConflictableTransactionError>(())
     })?;
+
+    for pun in new_puns {
+        index_punishment(pun, &user.uuid)?;
+    }
+
     Ok(())
 }
 
@@ -107,81 +163,235 @@ pub fn create_new_player(uuid: &str, name: &str) -> Result<User, BackendError> {
     Ok(user)
 }
 
-pub fn create_punishment(
-    user_uuid: &str,
-    title: &str,
-    r#type: &str,
-    creation_date: DateTime<Utc>,
-    expiration_date: Option<DateTime<Utc>>,
-    reason: &str,
-    alsoip: bool,
-    allow_chat: bool,
-    allow_ranked: bool,
-    allow_unranked: bool,
-    allow_join_minigames: bool
-) -> Result<Punishment, BackendError> {
+/**
+* Stores a new punishment for the user, the id is generated here so the one in the given
+* punishment is ignored. If the punishment also applies to the ip, the subnet of the user's
+* last known address is banned using the prefix length that matches its family.
+*/
+pub fn create_punishment(user_uuid: &str, mut punishment: Punishment, ipv4_prefix: u8, ipv6_prefix: u8) -> Result<Punishment, BackendError> {
+    if punishment.alsoip {
+        let address = get_last_address(user_uuid)?.ok_or(BackendError::new("IP not indexed", 400))?;
+
+        punishment.subnet = Some(Subnet::for_address(address, ipv4_prefix, ipv6_prefix)?);
+    }
+
     let tree = PUNISHMENTS.clone();
 
-    let punishment = tree.transaction(|db| {
-        let id = db.generate_id()?;
-        
-        let punishment = Punishment {
-            id, title: title.into(),
-            r#type: r#type.into(),
-            creation_date, expiration_date,
-            reason: reason.into(),
-            alsoip, allow_chat, allow_ranked,
-            allow_unranked, allow_join_minigames
-        };
-
-        db.insert(&id.to_be_bytes(), json::stringify(punishment.to_json()).as_bytes())?;
-
-        Ok::<Punishment, ConflictableTransactionError>(punishment)
-    })?;
+    punishment.id = get_client().generate_id()?;
+    tree.insert(punishment.id.to_be_bytes(), json::stringify(punishment.to_json()).as_bytes())?;
 
     let tree = USERS.clone();
     tree.insert(format!("{user_uuid}:punishments:{}", punishment.id), &punishment.id.to_be_bytes())?;
-    if punishment.alsoip {
-        let idx = IP_INDEXES.clone();
+    if let Some(subnet) = &punishment.subnet {
         let tree = IP_PUNISHMENTS.clone();
 
-        let ip = idx.get(user_uuid)?.ok_or(BackendError::new("IP not indexed", 400))?;
-        let subject_addr = from_utf8(&ip)?;
-
-        tree.insert(format!("{}:{}", subject_addr.get(0..subject_addr.rfind('.').ok_or(BackendError::new("Bad ip address", 400))?).unwrap(), punishment.id), &punishment.id.to_be_bytes())?;
+        tree.insert(format!("{subnet}:{}", punishment.id), &punishment.id.to_be_bytes())?;
+        IP_PREFIXES.insert(subnet.prefix_key(), &[])?;
     }
+    index_punishment(&punishment, user_uuid)?;
+    log_action(AuditAction::Issue, punishment.id, user_uuid, punishment.issuer.as_deref(), &punishment.reason)?;
+    publish(Event::UserPunished { uuid: user_uuid.into(), punishment_id: punishment.id });
 
     Ok(punishment)
 }
 
-pub fn unpunish_by_name(username: &str, punishment_id: u64) -> Result<(), BackendError> {
-    let maps = NAME_INDEXES.clone();
+/**
+* How many punishments the user has received from a template, revoked ones don't count.
+*/
+pub fn count_template_offences(uuid: &str, template: &str) -> Result<usize, BackendError> {
+    Ok(get_user_punishments(uuid)?.iter()
+        .filter(|p| p.template.as_deref() == Some(template) && !p.is_revoked())
+        .count())
+}
+
+/**
+* Revokes one of the user's punishments. The original expiration date is kept, the revocation is
+* recorded separately along with who did it and why, and written to the audit log.
+*/
+pub fn revoke_punishment(uuid: &str, punishment_id: u64, actor: Option<&str>, reason: &str) -> Result<Punishment, BackendError> {
     let users = USERS.clone();
 
-    if let Some(uuid) = maps.get(username.as_bytes())?
-        && users.get(format!("{}:punishments{punishment_id}", from_utf8(&uuid)?).as_bytes())?.is_some()
-        && let Some(mut punishment) = get_punishment(punishment_id)? {
-        let tree = PUNISHMENTS.clone();
-        punishment.expiration_date = Some(Utc::now());
-        tree.insert(punishment_id.to_be_bytes(), stringify(punishment.to_json()).as_bytes())?;
-        Ok(())
-    } else { Err(BackendError::new("Punishment not found", 404)) }
+    if users.get(format!("{uuid}:punishments:{punishment_id}").as_bytes())?.is_none() {
+        return Err(BackendError::new("Punishment not found", 404));
+    }
+
+    let mut punishment = get_punishment(punishment_id)?.ok_or(BackendError::new("Punishment not found", 404))?;
+    if punishment.is_revoked() {
+        return Err(BackendError::new("Punishment already revoked", 409));
+    }
+
+    punishment.revoked_at = Some(Utc::now());
+    punishment.revoked_by = actor.map(|a| a.into());
+    punishment.revoke_reason = Some(reason.into());
+
+    let tree = PUNISHMENTS.clone();
+    tree.insert(punishment_id.to_be_bytes(), stringify(punishment.to_json()).as_bytes())?;
+    index_punishment(&punishment, uuid)?;
+    log_action(AuditAction::Revoke, punishment_id, uuid, actor, reason)?;
+    publish(Event::PunishmentRevoked { uuid: uuid.into(), punishment_id });
+
+    Ok(punishment)
 }
 
+pub fn revoke_punishment_by_name(username: &str, punishment_id: u64, actor: Option<&str>, reason: &str) -> Result<Punishment, BackendError> {
+    let uuid = get_uuid_by_name(username)?.ok_or(BackendError::new("Punishment not found", 404))?;
+
+    revoke_punishment(&uuid, punishment_id, actor, reason)
+}
+
+/**
+* Links the name to the uuid, the user's name history ("{uuid}#" followed by the encoded date)
+* gets a new entry when the name differs from the last one recorded.
+*/
 pub fn set_name_index(name: &str, uuid: &str) -> Result<(), BackendError> {
     let tree = NAME_INDEXES.clone();
+    let history = NAME_HISTORY.clone();
 
     tree.insert(name, uuid)?;
+
+    let last = history.scan_prefix(format!("{uuid}#")).next_back().transpose()?;
+    if last.is_none_or(|(_, last_name)| *last_name != *name.as_bytes()) {
+        history.insert([format!("{uuid}#").as_bytes(), &encode_datetime(Utc::now())].concat(), name.as_bytes())?;
+    }
     Ok(())
 }
 
-pub fn set_ip_index(address: &str, uuid: &str) -> Result<(), BackendError> {
+/**
+* A name the user has connected with and the date it was first seen.
+*/
+pub type NameChange = (Box<str>, DateTime<Utc>);
+
+/**
+* Names the user has connected with, oldest first.
+*/
+pub fn get_name_history(uuid: &str) -> Result<Vec<NameChange>, BackendError> {
+    let history = NAME_HISTORY.clone();
+    let prefix = format!("{uuid}#");
+    let mut res = vec![];
+
+    for entry in history.scan_prefix(&prefix) {
+        let (key, name) = entry?;
+
+        res.push((from_utf8(&name)?.into(), decode_datetime(&key[prefix.len()..])?));
+    }
+
+    Ok(res)
+}
+
+/**
+* Records that the user connected from the address. Both the ip -> uuids index and the
+* uuid -> ips history are keyed by "{a}#{b}" and store when the pair was first and last seen.
+*/
+pub fn set_ip_index(address: &IpAddr, uuid: &str) -> Result<(), BackendError> {
     let tree = IP_INDEXES.clone();
+    let history = IP_HISTORY.clone();
+    let users = USERS.clone();
+    let address = address.to_string();
+    let now = encode_datetime(Utc::now());
+
+    let first_seen = history.get(format!("{uuid}#{address}"))?
+        .and_then(|v| v.get(0..7).map(|s| s.to_vec()))
+        .unwrap_or(now.to_vec());
+    let value = [first_seen.as_slice(), &now].concat();
 
-    tree.insert(address, uuid)?;
+    tree.insert(format!("{address}#{uuid}"), value.as_slice())?;
+    history.insert(format!("{uuid}#{address}"), value.as_slice())?;
+    users.insert(format!("{uuid}:address"), address.as_bytes())?;
     Ok(())
 }
 
+/**
+* An ip index entry: the address or uuid it links to, when it was first seen and last seen.
+*/
+pub type Sighting<T> = (T, DateTime<Utc>, DateTime<Utc>);
+
+fn decode_seen(value: &[u8]) -> Result<(DateTime<Utc>, DateTime<Utc>), BackendError> {
+    if value.len() < 14 {
+        return Err(BackendError::new("Corrupt ip index data", 500));
+    }
+
+    Ok((decode_datetime(&value[0..7])?, decode_datetime(&value[7..14])?))
+}
+
+pub fn get_last_address(uuid: &str) -> Result<Option<IpAddr>, BackendError> {
+    let users = USERS.clone();
+
+    match users.get(format!("{uuid}:address"))? {
+        Some(address) => Ok(Some(parse_address(from_utf8(&address)?)?)),
+        None => Ok(None)
+    }
+}
+
+/**
+* Every address the user has connected from, with the first and last time it was seen.
+*/
+pub fn get_user_addresses(uuid: &str) -> Result<Vec<Sighting<IpAddr>>, BackendError> {
+    let history = IP_HISTORY.clone();
+    let prefix = format!("{uuid}#");
+    let mut res = vec![];
+
+    for entry in history.scan_prefix(&prefix) {
+        let (key, value) = entry?;
+        let (first, last) = decode_seen(&value)?;
+
+        res.push((parse_address(from_utf8(&key[prefix.len()..])?)?, first, last));
+    }
+
+    Ok(res)
+}
+
+/**
+* Every user that has connected from the address, with the first and last time it was seen.
+*/
+pub fn get_address_users(address: &IpAddr) -> Result<Vec<Sighting<Box<str>>>, BackendError> {
+    let tree = IP_INDEXES.clone();
+    let prefix = format!("{address}#");
+    let mut res = vec![];
+
+    for entry in tree.scan_prefix(&prefix) {
+        let (key, value) = entry?;
+        let (first, last) = decode_seen(&value)?;
+
+        res.push((from_utf8(&key[prefix.len()..])?.into(), first, last));
+    }
+
+    Ok(res)
+}
+
+/**
+* Accounts that share at least one address with the user, most shared addresses first.
+*/
+pub fn get_alts(uuid: &str) -> Result<Vec<AltAccount>, BackendError> {
+    let users = USERS.clone();
+    let mut alts: Vec<AltAccount> = vec![];
+
+    for (address, _, _) in get_user_addresses(uuid)? {
+        for (alt_uuid, _, last_seen) in get_address_users(&address)? {
+            if alt_uuid.as_ref() == uuid {
+                continue;
+            }
+
+            if let Some(alt) = alts.iter_mut().find(|a| a.uuid == alt_uuid) {
+                alt.shared_ips += 1;
+                alt.last_seen = alt.last_seen.max(last_seen);
+                continue;
+            }
+
+            let name = match users.get(format!("{alt_uuid}:name"))? {
+                Some(name) => from_utf8(&name)?.into(),
+                None => "Unknown".into()
+            };
+            let now = Utc::now();
+            let punishments = get_user_punishments(&alt_uuid)?.into_iter().filter(|p| p.is_active(now)).collect();
+
+            alts.push(AltAccount { uuid: alt_uuid, name, shared_ips: 1, last_seen, punishments });
+        }
+    }
+
+    alts.sort_by(|a, b| b.shared_ips.cmp(&a.shared_ips).then(b.last_seen.cmp(&a.last_seen)));
+    Ok(alts)
+}
+
 pub fn get_uuid_by_name(name: &str) -> Result<Option<Box<str>>, BackendError> {
     let tree = NAME_INDEXES.clone();
 
@@ -198,28 +408,86 @@ pub fn get_user_by_name(name: &str) -> Result<Option<User>, BackendError> {
     get_user(uuid.unwrap().as_ref())
 }
 
-pub fn get_user_connected(uuid: &str, name: &str, address: &str) -> Result<User, BackendError> {
-    let mut user = get_user(uuid)?.unwrap_or(create_new_player(uuid, name)?);
-    set_name_index(name, uuid)?;
-    set_ip_index(address, uuid)?;
-
+/**
+* Get every punishment whose banned subnet contains the address, no matter the prefix length
+* it was issued with.
+*/
+pub fn get_ip_punishments(address: &IpAddr) -> Result<Vec<Punishment>, BackendError> {
     let tree = IP_PUNISHMENTS.clone();
-    let puns_tree = PUNISHMENTS.clone();
+    let mut puns = vec![];
+    let mut prefixes = vec![];
 
-    for p in tree.scan_prefix(&address[0..address.rfind('.').ok_or(BackendError::new("Bad ip address", 400))?])
-        .filter_map(|p| {
-            Punishment::from_json(
-                &json::parse(
-                    from_utf8(
-                        &puns_tree.get(p.ok()?.1).ok()??
-                    ).ok()?
-                ).ok()?
-            ).ok()
-        }) {
-        user.punishments.push(p);
+    for key in IP_PREFIXES.scan_prefix([family(&address.to_canonical())]).keys() {
+        prefixes.push(key?[1]);
     }
 
-    Ok(user)
+    for subnet in Subnet::enclosing(address, prefixes) {
+        for entry in tree.scan_prefix(format!("{subnet}:")) {
+            let raw: [u8; 8] = entry?.1.as_ref().try_into().map_err(|_| BackendError::new("Internal Error", 500))?;
+
+            if let Some(pun) = get_punishment(u64::from_be_bytes(raw))? {
+                puns.push(pun);
+            }
+        }
+    }
+
+    Ok(puns)
+}
+
+/**
+* Appends the punishments matching the address that aren't in the list yet, returns the ids of
+* the appended ones that are active bans. Those belong to other accounts, meaning the owner of
+* the list is likely evading a ban with an alt.
+*/
+fn merge_ip_punishments(punishments: &mut Vec<Punishment>, address: &IpAddr) -> Result<Vec<u64>, BackendError> {
+    let now = Utc::now();
+    let mut evaded = vec![];
+
+    for p in get_ip_punishments(address)? {
+        if !punishments.iter().any(|own| own.id == p.id) {
+            if p.is_ban() && p.is_active(now) {
+                evaded.push(p.id);
+            }
+            punishments.push(p);
+        }
+    }
+
+    Ok(evaded)
+}
+
+/**
+* The user's punishments plus the ones matching their last known address.
+*/
+pub fn get_effective_punishments(uuid: &str) -> Result<Vec<Punishment>, BackendError> {
+    let mut puns = get_user_punishments(uuid)?;
+
+    if let Some(address) = get_last_address(uuid)? {
+        merge_ip_punishments(&mut puns, &address)?;
+    }
+
+    Ok(puns)
+}
+
+/**
+* Loads (or creates) the user and indexes the connection. Punishments matching the address are
+* appended to the user's own, see merge_ip_punishments for the returned ids.
+*/
+pub fn get_user_connected(uuid: &str, name: &str, address: &str) -> Result<(User, Vec<u64>), BackendError> {
+    let address = parse_address(address)?;
+    let mut user = match get_user(uuid)? {
+        Some(user) => user,
+        None => create_new_player(uuid, name)?
+    };
+    if user.name.as_ref() != name {
+        USERS.insert(format!("{uuid}:name"), name)?;
+        user.name = name.into();
+    }
+    set_name_index(name, uuid)?;
+    set_ip_index(&address, uuid)?;
+
+    let evaded = merge_ip_punishments(&mut user.punishments, &address)?;
+
+    Ok((user, evaded))
 }
 
 pub fn get_default_group_name() -> Result<Option<IVec>, BackendError> {
@@ -235,6 +503,7 @@ pub fn get_default_group_name() -> Result<Option<IVec>, BackendError> {
 
 pub fn set_default_group(name: &str) -> Result<(), BackendError> {
     get_client().insert(b"default_group", name)?;
+    publish(Event::DefaultGroupChanged { group: name.into() });
 
     Ok(())
 }
@@ -245,18 +514,20 @@ pub fn put_permission_to_group(group_name: &str, perm: &Permission) -> Result<()
     }
 
     let tree = GROUPS.clone();
-    tree.insert(format!("{group_name}:permissions:{}", &perm.perm), &[perm.value as u8])?;
+    tree.insert(format!("{group_name}:permissions:{}", perm.storage_key()), &[perm.value as u8])?;
+    publish(Event::GroupUpdated { group: group_name.into() });
 
     Ok(())
 }
 
-pub fn delete_permission_from_group(group_name: &str, perm: &str) -> Result<(), BackendError> {
+pub fn delete_permission_from_group(group_name: &str, perm: &str, contexts: &Contexts) -> Result<(), BackendError> {
     if !group_exists(group_name)? {
         return Err(BackendError::new("Group doesn't exist", 404));
     }
 
     let tree = GROUPS.clone();
-    tree.remove(format!("{group_name}:permissions:{}", perm))?;
+    tree.remove(format!("{group_name}:permissions:{}", scoped_key(perm, contexts)))?;
+    publish(Event::GroupUpdated { group: group_name.into() });
 
     Ok(())
 }
@@ -270,23 +541,81 @@ pub fn remove_perms_from_group(group: &Group) -> Result<(), BackendError> {
     }
 
     tree.apply_batch(batch)?;
+    publish(Event::GroupUpdated { group: group.name.clone() });
 
     Ok(())
 }
 
+/**
+* Creates or updates the group, its parents are replaced by the given ones. Parents must exist
+* and can't make the group inherit from itself.
+*/
 pub fn put_group(group: &Group) -> Result<(), BackendError> {
     let tree = GROUPS.clone();
+    check_group_parents(group)?;
 
-    tree.insert(format!("{}:prefix", &group.name), group.prefix.as_bytes())?;
-    tree.insert(format!("{}:suffix", &group.name), group.suffix.as_bytes())?;
+    let mut batch = Batch::default();
+    for key in tree.scan_prefix(format!("{}:parents:", &group.name)).keys() {
+        batch.remove(key?);
+    }
+    batch.insert(format!("{}:prefix", &group.name).as_bytes(), group.prefix.as_bytes());
+    batch.insert(format!("{}:suffix", &group.name).as_bytes(), group.suffix.as_bytes());
+    batch.insert(format!("{}:weight", &group.name).as_bytes(), &group.weight.to_be_bytes());
 
+    for parent in &group.parents {
+        batch.insert(format!("{}:parents:{parent}", &group.name).as_bytes(), parent.as_bytes());
+    }
     for perm in &group.perms {
-        tree.insert(format!("{}:permissions:{}", &group.name, &perm.perm), &[perm.value as u8])?;
+        batch.insert(format!("{}:permissions:{}", &group.name, perm.storage_key()).as_bytes(), &[perm.value as u8]);
     }
+    tree.apply_batch(batch)?;
+    publish(Event::GroupUpdated { group: group.name.clone() });
 
     Ok(())
 }
 
+pub fn check_group_parents(group: &Group) -> Result<(), BackendError> {
+    for parent in &group.parents {
+        if !group_exists(parent)? {
+            return Err(BackendError::new(&format!("Parent group {parent} doesn't exist"), 400));
+        }
+        if inherits_from(parent, &group.name, &mut HashSet::new(), &get_group_parents)? {
+            return Err(BackendError::new(&format!("Inheriting from {parent} would create a cycle"), 400));
+        }
+    }
+    Ok(())
+}
+
+/**
+* Whether group is target or inherits from it, directly or not. parents_of reads the parents of
+* a group.
+*/
+fn inherits_from(group: &str, target: &str, visited: &mut HashSet<Box<str>>, parents_of: &impl Fn(&str) -> Result<Vec<Box<str>>, BackendError>) -> Result<bool, BackendError> {
+    if group == target {
+        return Ok(true);
+    }
+    if !visited.insert(group.into()) {
+        return Ok(false);
+    }
+
+    for parent in parents_of(group)? {
+        if inherits_from(&parent, target, visited, parents_of)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn get_group_parents(name: &str) -> Result<Vec<Box<str>>, BackendError> {
+    let tree = GROUPS.clone();
+    let mut parents = vec![];
+
+    for entry in tree.scan_prefix(format!("{name}:parents:")) {
+        parents.push(from_utf8(&entry?.1)?.into());
+    }
+    Ok(parents)
+}
+
 pub fn remove_group(group_name: &str) -> Result<bool, BackendError> {
     let tree = GROUPS.clone();
     let mut batch = Batch::default();
@@ -294,15 +623,65 @@ pub fn remove_group(group_name: &str) -> Result<bool, BackendError> {
         return Ok(false);
     }
 
-    for k in tree.scan_prefix(group_name).keys() {
+    for k in tree.scan_prefix(format!("{group_name}:")).keys() {
         batch.remove(k?);
     }
+    // Groups inheriting from it
+    let suffix = format!(":parents:{group_name}");
+    for k in tree.iter().keys() {
+        let k = k?;
+        if k.ends_with(suffix.as_bytes()) {
+            batch.remove(k);
+        }
+    }
 
     tree.apply_batch(batch)?;
+    publish(Event::GroupDeleted { group: group_name.into() });
     Ok(true)
 }
 
+/**
+* Loads the group with its declared and effective permissions.
+*/
 pub fn get_group_full(name: &str) -> Result<Option<Group>, BackendError> {
+    let Some(mut group) = get_group_declared(name)? else {
+        return Ok(None);
+    };
+
+    let mut visited = HashSet::from([group.name.clone()]);
+    group.effective_perms = inherit_perms(&group, &mut visited, &get_group_declared)?;
+
+    Ok(Some(group))
+}
+
+/**
+* The group's declared perms followed by the ones inherited from its parents that it doesn't
+* override, parents are visited by weight (highest first). A group is never visited twice, which
+* cuts inheritance cycles and diamonds. load reads a group without its inherited perms.
+*/
+fn inherit_perms(group: &Group, visited: &mut HashSet<Box<str>>, load: &impl Fn(&str) -> Result<Option<Group>, BackendError>) -> Result<Vec<Permission>, BackendError> {
+    let mut perms = group.perms.clone();
+    let mut parents = vec![];
+
+    for parent in &group.parents {
+        if visited.insert(parent.clone()) && let Some(parent) = load(parent)? {
+            parents.push(parent);
+        }
+    }
+    parents.sort_by_key(|p| Reverse(p.weight));
+
+    for parent in parents {
+        for perm in inherit_perms(&parent, visited, load)? {
+            if !perms.iter().any(|p| p.perm.eq_ignore_ascii_case(&perm.perm) && p.contexts == perm.contexts) {
+                perms.push(perm);
+            }
+        }
+    }
+
+    Ok(perms)
+}
+
+fn get_group_declared(name: &str) -> Result<Option<Group>, BackendError> {
     let tree = GROUPS.clone();
 
     let prefix = tree.get(format!("{name}:prefix"))?;
@@ -315,16 +694,26 @@ pub fn get_group_full(name: &str) -> Result<Option<Group>, BackendError> {
     let suffix = suffix.unwrap();
     let mut perms = vec![];
 
-    for key in tree.scan_prefix(format!("{name}:permissions:")) {
-        let (perm, value) = key?;
-        perms.push(Permission { perm: from_utf8(&perm)?.into(), value: value[0] != 0 });
+    let perm_prefix = format!("{name}:permissions:");
+    for key in tree.scan_prefix(&perm_prefix) {
+        let (key, value) = key?;
+        let (perm, contexts) = parse_scoped_key(from_utf8(&key[perm_prefix.len()..])?)?;
+        perms.push(Permission { perm: perm.into(), value: value[0] != 0, contexts, expires_at: None });
     }
 
+    let weight = match tree.get(format!("{name}:weight"))? {
+        Some(raw) => i32::from_be_bytes(raw.as_ref().try_into().map_err(|_| BackendError::new("Corrupt group weight", 500))?),
+        None => 0
+    };
+
     Ok(Some(Group {
         name: name.into(),
         prefix: from_utf8(&prefix)?.into(),
         suffix: from_utf8(&suffix)?.into(),
         perms,
+        parents: get_group_parents(name)?,
+        weight,
+        effective_perms: vec![]
     }))
 }
 
@@ -342,6 +731,7 @@ pub fn get_all_groups_full() -> Result<Vec<Group>, BackendError> {
     for name in set {
         res.push(get_group_full(&name)?.ok_or(BackendError::new("Error", 500))?);
     }
+    res.sort_by(|a, b| b.weight.cmp(&a.weight).then_with(|| a.name.cmp(&b.name)));
     Ok(res)
 }
 
@@ -358,17 +748,38 @@ fn get_friends(uuid: &str, tree: &Arc<Tree>) -> Result<Vec<UserMapping>, Backend
     Ok(friends)
 }
 
-fn get_group_from_opt(mut name_opt: Option<IVec>) -> Result<Option<Group>, BackendError> {
-    if name_opt.is_none() {
-        name_opt = get_default_group_name()?;
-        if name_opt.is_none() {
-            return Ok(None);
+pub fn get_user_friends(uuid: &str) -> Result<Vec<UserMapping>, BackendError> {
+    get_friends(uuid, &USERS.clone())
+}
+
+/**
+* Groups held by the user, highest weight first. Expired memberships that weren't swept yet
+* are skipped, users without groups get the default one.
+*/
+fn get_user_groups(uuid: &str, tree: &Arc<Tree>) -> Result<Vec<Membership>, BackendError> {
+    let mut groups = vec![];
+    let now = Utc::now();
+
+    let prefix = format!("{uuid}:groups:");
+    for entry in tree.scan_prefix(&prefix) {
+        let (key, _) = entry?;
+        let key = from_utf8(&key[prefix.len()..])?;
+        let expires_at = get_grant_expiry(uuid, GrantKind::Group, key)?;
+        if expires_at.is_some_and(|e| e <= now) {
+            continue;
         }
-    }
 
-    let name = unsafe {name_opt.unwrap_unchecked()};
+        let (name, contexts) = parse_scoped_key(key)?;
+        if let Some(group) = get_group_full(name)? {
+            groups.push(Membership { group, contexts, expires_at });
+        }
+    }
+    if groups.is_empty() && let Some(name) = get_default_group_name()? && let Some(group) = get_group_full(from_utf8(&name)?)? {
+        groups.push(Membership { group, contexts: Contexts::default(), expires_at: None });
+    }
 
-    get_group_full(from_utf8(&name)?)
+    groups.sort_by_key(|m| Reverse(m.group.weight));
+    Ok(groups)
 }
 
 fn get_ignores(uuid: &str, tree: &Arc<Tree>) -> Result<Vec<UserMapping>, BackendError> {
@@ -384,6 +795,62 @@ fn get_ignores(uuid: &str, tree: &Arc<Tree>) -> Result<Vec<UserMapping>, Backend
     Ok(ignores)
 }
 
+/**
+* Appends a mail to the user's inbox and returns the id it was given, inboxes are only changed
+* here and in update_mail.
+*/
+pub fn push_mail(uuid: &str, mail: &dyn Mail) -> Result<u64, BackendError> {
+    if !user_exists(uuid)? {
+        return Err(BackendError::new("User not found", 404));
+    }
+
+    let tree = USERS.clone();
+    let id = get_client().generate_id()?;
+    let mut mail = mail.to_json();
+    mail["id"] = id.into();
+
+    tree.fetch_and_update(format!("{uuid}:mails"), |old| {
+        let mut inbox = old.and_then(|o| json::parse(from_utf8(o).ok()?).ok()).unwrap_or(JsonValue::new_array());
+        if !inbox.is_array() {
+            inbox = JsonValue::new_array();
+        }
+        let _ = inbox.push(mail.clone());
+
+        Some(stringify(inbox).into_bytes())
+    })?;
+
+    Ok(id)
+}
+
+/**
+* Sets the deleted and claimed flags of a mail, deleting a mail only flags it and claimed only
+* matters for coins. Returns false when the user has no mail with that id.
+*/
+pub fn update_mail(uuid: &str, id: u64, deleted: Option<bool>, claimed: Option<bool>) -> Result<bool, BackendError> {
+    let tree = USERS.clone();
+    let mut found = false;
+
+    tree.fetch_and_update(format!("{uuid}:mails"), |old| {
+        let old = old?;
+        let Some(mut inbox) = from_utf8(old).ok().and_then(|o| json::parse(o).ok()) else { return Some(old.to_vec()) };
+
+        found = false;
+        for mail in inbox.members_mut().filter(|m| m["id"].as_u64() == Some(id)) {
+            if let Some(deleted) = deleted {
+                mail["deleted"] = deleted.into();
+            }
+            if let Some(claimed) = claimed {
+                mail["claimed"] = claimed.into();
+            }
+            found = true;
+        }
+
+        Some(stringify(inbox).into_bytes())
+    })?;
+
+    Ok(found)
+}
+
 fn get_user_mails(uuid: &str, tree: &Arc<Tree>) -> Result<Vec<Box<dyn Mail>>, BackendError> {
     let opt = tree.get(format!("{uuid}:mails"))?;
     if opt.is_none() {
@@ -395,18 +862,87 @@ fn get_user_mails(uuid: &str, tree: &Arc<Tree>) -> Result<Vec<Box<dyn Mail>>, Ba
     Ok(get_mails_from_json(&json))
 }
 
+/**
+* Permissions of the user, timed ones that expired but weren't swept yet are skipped.
+*/
 fn get_user_permissions(uuid: &str, tree: &Arc<Tree>) -> Result<Vec<Permission>, BackendError> {
     let mut perms = vec![];
+    let now = Utc::now();
 
     let prefix = format!("{uuid}:permissions:");
     for perm in tree.scan_prefix(&prefix) {
         let (key, value) = perm?;
-        perms.push(Permission { perm: from_utf8(&key[prefix.len()..])?.into(), value: value[0] != 0 });
+        let key = from_utf8(&key[prefix.len()..])?;
+        let expires_at = get_grant_expiry(uuid, GrantKind::Permission, key)?;
+        if expires_at.is_some_and(|e| e <= now) {
+            continue;
+        }
+
+        let (perm, contexts) = parse_scoped_key(key)?;
+        perms.push(Permission { perm: perm.into(), value: value[0] != 0, contexts, expires_at });
     }
 
     Ok(perms)
 }
 
+/**
+* Grants a permission node to the user (or updates its value and expiry), timed if
+* perm.expires_at is set.
+*/
+pub fn put_permission_to_user(uuid: &str, perm: &Permission) -> Result<(), BackendError> {
+    if !user_exists(uuid)? {
+        return Err(BackendError::new("User not found", 404));
+    }
+
+    let tree = USERS.clone();
+    let key = perm.storage_key();
+    tree.insert(format!("{uuid}:permissions:{key}"), &[perm.value as u8])?;
+    set_grant_expiry(uuid, GrantKind::Permission, &key, perm.expires_at)?;
+    publish(Event::UserPermissionsChanged { uuid: uuid.into() });
+
+    Ok(())
+}
+
+pub fn delete_permission_from_user(uuid: &str, perm: &str, contexts: &Contexts) -> Result<(), BackendError> {
+    let tree = USERS.clone();
+    let key = scoped_key(perm, contexts);
+
+    if tree.remove(format!("{uuid}:permissions:{key}"))?.is_none() {
+        return Err(BackendError::new("The user doesn't have that permission", 404));
+    }
+    set_grant_expiry(uuid, GrantKind::Permission, &key, None)?;
+    publish(Event::UserPermissionsChanged { uuid: uuid.into() });
+
+    Ok(())
+}
+
+/**
+* The user's own permission nodes, None if the user doesn't exist.
+*/
+pub fn get_permissions_of_user(uuid: &str) -> Result<Option<Vec<Permission>>, BackendError> {
+    let tree = USERS.clone();
+    if !tree.contains_key(format!("{uuid}:name"))? {
+        return Ok(None);
+    }
+
+    Ok(Some(get_user_permissions(uuid, &tree)?))
+}
+
+/**
+* Loads only what is needed to check the user's permissions, None if the user doesn't exist.
+*/
+pub fn get_permission_holder(uuid: &str) -> Result<Option<PermissionHolder>, BackendError> {
+    let tree = USERS.clone();
+    if !tree.contains_key(format!("{uuid}:name"))? {
+        return Ok(None);
+    }
+
+    Ok(Some(PermissionHolder {
+        perms: get_user_permissions(uuid, &tree)?,
+        groups: get_user_groups(uuid, &tree)?
+    }))
+}
+
 pub fn user_remove_friend(uuid1: &str, uuid2: &str) -> Result<(), BackendError> {
     let tree = USERS.clone();
 
@@ -416,7 +952,7 @@ pub fn user_remove_friend(uuid1: &str, uuid2: &str) -> Result<(), BackendError>
     Ok(())
 }
 
-fn get_user_punishments(uuid: &str) -> Result<Vec<Punishment>, BackendError> {
+pub fn get_user_punishments(uuid: &str) -> Result<Vec<Punishment>, BackendError> {
     let mut puns = vec![];
 
     let prefix = format!("{uuid}:punishments:");
@@ -435,7 +971,7 @@ fn get_user_punishments(uuid: &str) -> Result<Vec<Punishment>, BackendError> {
     Ok(puns)
 }
 
-fn get_punishment(id: u64) -> Result<Option<Punishment>, BackendError> {
+pub(super) fn get_punishment(id: u64) -> Result<Option<Punishment>, BackendError> {
     let tree = PUNISHMENTS.clone();
 
     if let Some(punishment) = tree.get(id.to_be_bytes())? {
@@ -476,7 +1012,7 @@ pub fn get_user(uuid: &str) -> Result<Option<User>, BackendError> {
     let inbox: Vec<Box<dyn Mail>> = get_user_mails(uuid, &tree)?;
     let punishments = get_user_punishments(uuid)?;
     let perms: Vec<Permission> = get_user_permissions(uuid, &tree)?;
-    let group = get_group_from_opt(tree.get(format!("{uuid}:group"))?)?;
+    let groups = get_user_groups(uuid, &tree)?;
 
     let user = User {
         uuid: uuid.into(),
@@ -487,7 +1023,89 @@ pub fn get_user(uuid: &str) -> Result<Option<User>, BackendError> {
         lang: lang.into(),
         scoreboard, coins, friend_reqs, dnd,
         created_at, friends, ignores,
-        inbox, punishments, perms, group
+        inbox, punishments, perms, groups
     };
     Ok(Some(user))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Name, weight, parents and declared perms
+    type GroupSpec<'a> = (&'a str, i32, &'a [&'a str], &'a [(&'a str, bool)]);
+
+    const GROUPS: &[GroupSpec] = &[
+        ("default", 0, &[], &[("chat", true), ("fly", false)]),
+        ("builder", 10, &["default"], &[("build", true), ("worldedit", false)]),
+        ("vip", 20, &["default"], &[("fly", true), ("worldedit", true)]),
+        ("staff", 30, &["builder", "vip"], &[("kick", true)]),
+        ("loop_a", 0, &["loop_b"], &[("a", true)]),
+        ("loop_b", 0, &["loop_a"], &[("b", true)])
+    ];
+
+    fn load(name: &str) -> Result<Option<Group>, BackendError> {
+        Ok(GROUPS.iter().find(|g| g.0 == name).map(|(name, weight, parents, perms)| {
+            let mut group = Group::new(name);
+            group.weight = *weight;
+            group.parents = parents.iter().map(|p| (*p).into()).collect();
+            group.perms = perms.iter().map(|(perm, value)| Permission { perm: (*perm).into(), value: *value, contexts: Contexts::default(), expires_at: None }).collect();
+            group
+        }))
+    }
+
+    fn parents_of(name: &str) -> Result<Vec<Box<str>>, BackendError> {
+        Ok(load(name)?.map(|g| g.parents).unwrap_or_default())
+    }
+
+    fn effective(name: &str) -> Vec<(String, bool)> {
+        let group = load(name).unwrap().unwrap();
+        let mut visited = HashSet::from([group.name.clone()]);
+
+        inherit_perms(&group, &mut visited, &load).unwrap().into_iter().map(|p| (p.perm.into(), p.value)).collect()
+    }
+
+    fn value_of(perms: &[(String, bool)], perm: &str) -> Option<bool> {
+        perms.iter().find(|(p, _)| p == perm).map(|(_, v)| *v)
+    }
+
+    #[test]
+    fn cycles_are_detected() {
+        assert!(inherits_from("staff", "default", &mut HashSet::new(), &parents_of).unwrap());
+        assert!(inherits_from("vip", "vip", &mut HashSet::new(), &parents_of).unwrap());
+        assert!(!inherits_from("default", "staff", &mut HashSet::new(), &parents_of).unwrap());
+        assert!(!inherits_from("builder", "vip", &mut HashSet::new(), &parents_of).unwrap());
+        // A cycle already in storage doesn't loop forever
+        assert!(!inherits_from("loop_a", "staff", &mut HashSet::new(), &parents_of).unwrap());
+    }
+
+    #[test]
+    fn declared_perms_override_inherited_ones() {
+        let vip = effective("vip");
+
+        assert_eq!(value_of(&vip, "fly"), Some(true));
+        assert_eq!(value_of(&vip, "chat"), Some(true));
+        assert_eq!(vip.iter().filter(|(p, _)| p == "fly").count(), 1);
+    }
+
+    #[test]
+    fn heavier_parents_come_first() {
+        let staff = effective("staff");
+
+        assert_eq!(value_of(&staff, "kick"), Some(true));
+        assert_eq!(value_of(&staff, "build"), Some(true));
+        // vip outweighs builder, and its own fly node overrides the one it inherits
+        assert_eq!(value_of(&staff, "worldedit"), Some(true));
+        assert_eq!(value_of(&staff, "fly"), Some(true));
+    }
+
+    #[test]
+    fn diamonds_and_cycles_are_visited_once() {
+        let staff = effective("staff");
+        assert_eq!(staff.iter().filter(|(p, _)| p == "chat").count(), 1);
+
+        let loop_a = effective("loop_a");
+        assert_eq!(loop_a.len(), 2);
+        assert_eq!(value_of(&loop_a, "b"), Some(true));
+    }
+}
@@ -0,0 +1,143 @@
+use std::{str::from_utf8, sync::{Arc, LazyLock}};
+
+use chrono::Utc;
+use json::stringify;
+use sled::Tree;
+
+use crate::api::typedef::{BackendError, jsonutils::SerializableJson, report::{Report, ReportStatus}};
+use super::{query::get_punishment, setup::get_client};
+
+static REPORTS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("reports").expect("Failed to open 'reports' tree")));
+// "{target uuid}:" followed by the report id in big endian
+static TARGET_REPORTS: LazyLock<Arc<Tree>> = LazyLock::new(|| Arc::new(get_client().open_tree("treports").expect("Failed to open 'treports' tree")));
+
+pub struct ReportFilter<'a> {
+    pub status: Option<ReportStatus>,
+    pub target: Option<&'a str>,
+    pub category: Option<&'a str>,
+    pub limit: usize
+}
+
+fn save_report(report: &Report) -> Result<(), BackendError> {
+    let tree = REPORTS.clone();
+
+    tree.insert(report.id.to_be_bytes(), stringify(report.to_json()).as_bytes())?;
+    Ok(())
+}
+
+pub fn get_report(id: u64) -> Result<Option<Report>, BackendError> {
+    let tree = REPORTS.clone();
+
+    match tree.get(id.to_be_bytes())? {
+        Some(raw) => Ok(Some(Report::from_json(&json::parse(from_utf8(&raw)?)?)?)),
+        None => Ok(None)
+    }
+}
+
+pub fn create_report(reporter: &str, target: &str, reason: &str, category: &str, server: &str, evidence: Vec<Box<str>>) -> Result<Report, BackendError> {
+    let now = Utc::now();
+    let report = Report {
+        id: get_client().generate_id()?,
+        reporter: reporter.into(),
+        target: target.into(),
+        reason: reason.into(),
+        category: category.into(),
+        server: server.into(),
+        evidence,
+        status: ReportStatus::Open,
+        claimed_by: None, resolution: None, punishment_id: None,
+        created_at: now, updated_at: now
+    };
+
+    save_report(&report)?;
+    TARGET_REPORTS.insert([format!("{target}:").as_bytes(), &report.id.to_be_bytes()].concat(), &report.id.to_be_bytes())?;
+
+    Ok(report)
+}
+
+pub fn get_reports_against(target: &str) -> Result<Vec<Report>, BackendError> {
+    let tree = TARGET_REPORTS.clone();
+    let mut res = vec![];
+
+    for entry in tree.scan_prefix(format!("{target}:")) {
+        let raw: [u8; 8] = entry?.1.as_ref().try_into().map_err(|_| BackendError::new("Internal Error", 500))?;
+
+        if let Some(report) = get_report(u64::from_be_bytes(raw))? {
+            res.push(report);
+        }
+    }
+
+    Ok(res)
+}
+
+/**
+* Get reports newest first.
+*/
+pub fn list_reports(filter: &ReportFilter) -> Result<Vec<Report>, BackendError> {
+    let matches = |r: &Report| filter.status.is_none_or(|s| s == r.status) && filter.category.is_none_or(|c| c == r.category.as_ref());
+
+    if let Some(target) = filter.target {
+        let mut reports = get_reports_against(target)?;
+        reports.reverse();
+
+        return Ok(reports.into_iter().filter(matches).take(filter.limit).collect());
+    }
+
+    let mut res = vec![];
+    for entry in REPORTS.iter().rev() {
+        let (_, raw) = entry?;
+        let report = Report::from_json(&json::parse(from_utf8(&raw)?)?)?;
+
+        if matches(&report) {
+            res.push(report);
+            if res.len() >= filter.limit {
+                break;
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+/**
+* Assigns an open report to a staff member, a claimed report can only be claimed again by the
+* same staff member.
+*/
+pub fn claim_report(id: u64, staff: &str) -> Result<Report, BackendError> {
+    let mut report = get_report(id)?.ok_or(BackendError::new("Report not found", 404))?;
+
+    match report.status {
+        ReportStatus::Resolved => return Err(BackendError::new("This report has already been resolved", 409)),
+        ReportStatus::Claimed if report.claimed_by.as_deref() != Some(staff) => return Err(BackendError::new("This report has already been claimed", 409)),
+        _ => {}
+    }
+
+    report.status = ReportStatus::Claimed;
+    report.claimed_by = Some(staff.into());
+    report.updated_at = Utc::now();
+    save_report(&report)?;
+
+    Ok(report)
+}
+
+/**
+* Closes a report, optionally linking the punishment issued because of it.
+*/
+pub fn resolve_report(id: u64, staff: &str, resolution: &str, punishment_id: Option<u64>) -> Result<Report, BackendError> {
+    let mut report = get_report(id)?.ok_or(BackendError::new("Report not found", 404))?;
+    if report.status == ReportStatus::Resolved {
+        return Err(BackendError::new("This report has already been resolved", 409));
+    }
+    if let Some(pid) = punishment_id && get_punishment(pid)?.is_none() {
+        return Err(BackendError::new("Punishment not found", 404));
+    }
+
+    report.status = ReportStatus::Resolved;
+    report.claimed_by = Some(report.claimed_by.unwrap_or(staff.into()));
+    report.resolution = Some(resolution.into());
+    report.punishment_id = punishment_id;
+    report.updated_at = Utc::now();
+    save_report(&report)?;
+
+    Ok(report)
+}
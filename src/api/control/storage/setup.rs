@@ -2,7 +2,9 @@ use std::{error::Error, sync::{Arc, LazyLock}};
 
 use sled::{open, Db};
 
-static DB_VERSION: u8 = 0;
+use super::migrations::migrate;
+
+static DB_VERSION: u8 = 5;
 
 static CLIENT: LazyLock<Arc<Db>> = LazyLock::new(|| Arc::new(open("data").expect("Failed to create database, likely a permissions problem")));
 
@@ -11,18 +13,16 @@ pub fn get_client() -> Arc<Db> {
 }
 
 /**
-* Change when there are data updates.
+* Bump DB_VERSION and add a step to migrations::migrate when there are data updates.
 */
-fn update() {}
-
 pub async fn init_db() -> Result<(), Box<dyn Error + Send + Sync>> {
-    let ret = CLIENT.insert("db_version", &[DB_VERSION])?;
+    let ret = CLIENT.get("db_version")?;
 
-    if let Some(prev) = ret {
-        if prev[0] != DB_VERSION {
-            update();
-        }
+    if let Some(prev) = ret && prev[0] < DB_VERSION {
+        println!("Migrating database from version {} to {DB_VERSION}...", prev[0]);
+        migrate(prev[0])?;
     }
 
+    CLIENT.insert("db_version", &[DB_VERSION])?;
     Ok(())
 }
@@ -0,0 +1,113 @@
+use std::{convert::Infallible, error::Error};
+
+use http_body_util::combinators::BoxBody;
+use hyper::{Request, Response, body::{Bytes, Incoming}, header::AUTHORIZATION};
+use json::{JsonValue, object};
+
+use crate::api::{control::storage::appeals::{comment_appeal, create_appeal, get_user_appeals, list_appeals, resolve_appeal}, routers::users::{check_session, check_session_token}, typedef::{BackendError, appeal::AppealStatus, jsonutils::SerializableJson, routing::{Method, nodes::Node}}, utils::{HttpTransaction, get_body_json, get_body_url_args, get_optional_url_args, response_json}};
+
+/**
+* Submit an appeal for one of the player's punishments.
+*
+* Method: POST
+* Headers: Authorization: <minecraft token of the player>
+*
+* Expects: body {
+*   uuid: string,
+*   punishment_id: u64,
+*   message: string
+* }
+*/
+async fn submit(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let token: Box<str> = req.headers().get(AUTHORIZATION)
+        .ok_or(BackendError::new("Authorization header missing", 401))?
+        .to_str().map_err(|_| BackendError::new("Failed to parse header", 500))?.into();
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+
+    let uuid = json["uuid"].as_str().ok_or(BackendError::new("uuid missing", 400))?;
+    let punishment_id = json["punishment_id"].as_u64().ok_or(BackendError::new("punishment_id missing", 400))?;
+    let message = json["message"].as_str().ok_or(BackendError::new("message missing", 400))?;
+
+    check_session_token(&token, uuid).await?;
+
+    Ok(response_json(create_appeal(uuid, punishment_id, message)?.to_json()))
+}
+
+/**
+* List the appeals of the player, requires the player's token.
+*/
+async fn mine(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let uuid = args.get("uuid").ok_or(BackendError::new("Malformed url, uuid param is required", 400))?;
+
+    check_session(&req, uuid).await?;
+
+    Ok(response_json(object! {
+        appeals: JsonValue::Array(get_user_appeals(uuid)?.iter().map(|a| a.to_json()).collect())
+    }))
+}
+
+/**
+* List appeals newest first, optionally by player (uuid) or status (pending, accepted, denied).
+*/
+async fn list(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_optional_url_args(&req)?;
+
+    let appeals = if let Some(uuid) = args.get("uuid") {
+        get_user_appeals(uuid)?
+    } else {
+        let status = match args.get("status") {
+            Some(s) => Some(AppealStatus::try_from(s.as_ref())?),
+            None => None
+        };
+        let limit = args.get("limit").map(|l| l.parse::<usize>()).transpose().map_err(|_| BackendError::new("limit invalid", 400))?.unwrap_or(50).min(500);
+
+        list_appeals(status, limit)?
+    };
+
+    Ok(response_json(object! {
+        appeals: JsonValue::Array(appeals.iter().map(|a| a.to_json()).collect())
+    }))
+}
+
+async fn comment(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+    let id = json["id"].as_u64().ok_or(BackendError::new("id missing", 400))?;
+    let author = json["author"].as_str().ok_or(BackendError::new("author missing", 400))?;
+    let text = json["text"].as_str().ok_or(BackendError::new("text missing", 400))?;
+
+    Ok(response_json(comment_appeal(id, author, text)?.to_json()))
+}
+
+async fn resolve(req: Request<Incoming>, accept: bool) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+    let id = json["id"].as_u64().ok_or(BackendError::new("id missing", 400))?;
+    let actor = json["actor"].as_str().ok_or(BackendError::new("actor missing", 400))?;
+    let resolution = json["resolution"].as_str().unwrap_or("");
+
+    Ok(response_json(resolve_appeal(id, accept, actor, resolution)?.to_json()))
+}
+
+/**
+* Player facing endpoints, authenticated with the player's session token.
+*/
+pub async fn register(node: &mut Node) -> Result<(), Box<dyn Error + Send + Sync>> {
+    node.subnode("/appeals")?
+        .endpoint("/submit", Method::Post, submit)?
+        .endpoint("/mine", Method::Get, mine)?;
+
+    Ok(())
+}
+
+/**
+* Staff endpoints, registered under the privileged core node.
+*/
+pub fn register_privileged(core: &mut Node) -> Result<(), Box<dyn Error + Send + Sync>> {
+    core.subnode("/appeals")?
+        .endpoint("/list", Method::Get, list)?
+        .endpoint("/comment", Method::Put, comment)?
+        .endpoint("/accept", Method::Put, |req| resolve(req, true))?
+        .endpoint("/deny", Method::Put, |req| resolve(req, false))?;
+
+    Ok(())
+}
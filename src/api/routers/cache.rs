@@ -0,0 +1,40 @@
+use std::{convert::Infallible, error::Error};
+
+use http_body_util::combinators::BoxBody;
+use hyper::{Request, Response, body::{Bytes, Incoming}};
+use json::JsonValue;
+
+use crate::api::{control::cache::{get_entry, list_entries}, typedef::{BackendError, routing::{Method, nodes::Node}}, utils::{get_body_url_args, get_optional_url_args, response_json}};
+
+/**
+* Live cache entries without their payloads, only those of channel if given.
+*/
+async fn list(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_optional_url_args(&req)?;
+    let entries = list_entries(args.get("channel").map(|c| c.as_ref())).await;
+
+    Ok(response_json(JsonValue::Array(entries.iter().map(|((channel, key), entry)| entry.to_json(channel, key, false)).collect())))
+}
+
+/**
+* A cache entry with its payload, null if it isn't utf-8.
+*/
+async fn get(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let channel = args.get("channel").ok_or(BackendError::new("channel missing", 400))?;
+    let key = args.get("key").ok_or(BackendError::new("key missing", 400))?;
+    let entry = get_entry(channel, key).await.ok_or(BackendError::new("Cache entry not found", 404))?;
+
+    Ok(response_json(entry.to_json(channel, key, true)))
+}
+
+/**
+* Cache inspection, registered under the privileged core node.
+*/
+pub fn register_privileged(core: &mut Node) -> Result<(), Box<dyn Error + Send + Sync>> {
+    core.subnode("/cache")?
+        .endpoint("/list", Method::Get, list)?
+        .endpoint("/get", Method::Get, get)?;
+
+    Ok(())
+}
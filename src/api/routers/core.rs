@@ -1,16 +1,56 @@
-use std::{collections::HashMap, convert::Infallible, error::Error, str::from_utf8, sync::Arc, time::Duration};
+use std::{collections::HashMap, convert::Infallible, error::Error, str::from_utf8, sync::{Arc, LazyLock, atomic::{AtomicU32, AtomicU64, Ordering}}, time::Duration};
 
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
 use futures::{SinkExt, StreamExt};
 use http_body_util::combinators::BoxBody;
-use hyper::{Request, Response, Version, body::{Buf, Bytes, Incoming}, header::AUTHORIZATION};
+use hyper::{Request, Response, Version, body::{Bytes, Incoming}, header::AUTHORIZATION};
 use json::{JsonValue, object};
-use tokio::{sync::{Mutex, mpsc::{UnboundedSender, unbounded_channel}}, task::JoinHandle};
-use tokio_util::bytes::{BufMut, BytesMut};
+use tokio::sync::{Mutex, broadcast::error::RecvError};
+use tokio_util::sync::CancellationToken;
 use tungstenite::{Message, protocol::WebSocketConfig};
 
-use crate::api::{control::{ioutils::{encode_msg, read_prefixed_string}, storage::query::{delete_permission_from_group, get_group_full, put_group, put_permission_to_group, remove_group, remove_perms_from_group, set_default_group, set_group_to_user, set_group_to_user_by_name, unpunish_by_name, user_remove_friend}}, typedef::{CacheData, permissions::{Group, Permission}, routing::nodes::Node}};
-use crate::api::{control::storage::query::{create_punishment, get_all_groups_full, get_default_group_name, get_user, get_user_connected, put_user}, typedef::{BackendError, User, jsonutils::SerializableJson, routing::Method}, utils::{HttpTransaction, get_body_json, get_body_url_args, response_json}};
+use crate::api::{routers::{appeals, cache, messages, notes, presence, reports}, control::{cache::{delete_entry, drop_owned_entries, read_entry, remove_expired_entries, write_entry}, events::subscribe, inotify::DirWatcher, outbound::OutboundQueue, presence::{clear_server, player_joined, player_quit}, registry::{list_servers, register_server, unregister_server, update_server_status}, pubsub::{add_subscription, get_subscribers, remove_subscription, remove_subscriptions, validate_channel}, rpc::{close_request, fail_requests, open_request, reply}, storage::query::{add_group_to_user, put_permission_to_user, delete_permission_from_user, get_permissions_of_user, check_group_parents, remove_group_from_user, delete_permission_from_group, get_permission_holder, get_group_full, put_group, put_permission_to_group, remove_group, remove_perms_from_group, get_alts, get_effective_punishments, count_template_offences, user_exists, set_default_group, set_group_to_user, set_group_to_user_by_name, revoke_punishment, revoke_punishment_by_name, user_remove_friend}, storage::audit::{AuditFilter, query_audit}, storage::grants::remove_expired_grants, storage::punishment_index::{PunishmentFilter, search_punishments as query_punishments}}, typedef::{audit::AuditAction, protocol::{ClientPacket, ErrorCode, PROTOCOL_VERSION, PacketError, RpcStatus, ServerPacket}, fs_json::{Config, identities::WsIdentities, templates::PunishmentTemplates, websocket::WsSettings}, network::{DEFAULT_IPV4_PREFIX, DEFAULT_IPV6_PREFIX, IPV4_PREFIXES, IPV6_PREFIXES, read_prefix}, permissions::{Contexts, Group, Permission}, punishment::{Punishment, Restrictions}, routing::nodes::Node}};
+use crate::api::{control::storage::query::{create_punishment, get_all_groups_full, get_default_group_name, get_user, get_user_connected, put_user}, typedef::{BackendError, User, jsonutils::SerializableJson, routing::Method}, utils::{HttpTransaction, get_body_json, get_body_url_args, get_optional_url_args, response_json}};
+
+pub type Clients = HashMap<Box<str>, Session>;
+
+/**
+* A connected websocket client, id tells apart sessions with the same name when one takes
+* over another. Cancelling kick ends the session, missed_pongs counts the pings sent since the
+* client was last heard from and max_missed_pongs is the setting it connected with.
+*/
+pub struct Session {
+    pub id: u64,
+    pub queue: OutboundQueue,
+    kick: CancellationToken,
+    missed_pongs: Arc<AtomicU32>,
+    max_missed_pongs: u32
+}
+
+impl Session {
+    /**
+    * Whether the client missed as many pongs as max_missed_pongs allows, it would be evicted
+    * on the next ping and can be taken over. The last ping may still be in flight, so at least
+    * two are needed.
+    */
+    fn is_stale(&self) -> bool {
+        self.missed_pongs.load(Ordering::Relaxed) >= self.max_missed_pongs.max(2)
+    }
+}
+
+/**
+* Whether a new session can connect as name, with takeover it can replace a stale one.
+*/
+fn can_connect(clients: &Clients, name: &str, takeover: bool) -> bool {
+    clients.get(name).is_none_or(|s| takeover && s.is_stale())
+}
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+/**
+* Websocket clients connected through /api/core/create_ws, by name.
+*/
+pub static CLIENTS: LazyLock<Arc<Mutex<Clients>>> = LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
 
 static TOKEN: &str = env!("PRIVILEGE_TOKEN");
 static ALLOWED_IP: &str = env!("PRIVILEGED_AUTHORIZED_IP");
@@ -41,6 +81,9 @@ fn privileged_middleware(req: &Request<Incoming>) -> Result<(), BackendError> {
 
 /**
 * Punish a player, this creates a punishment, assigns it to the player and returns it.
+* If alsoip is set, the player's subnet is punished too, the prefix lengths can be set with
+* ipv4_prefix (8 to 32, default 24) and ipv6_prefix (32 to 128, default 64), use 32/128 to
+* punish the exact ip.
 */
 async fn punish(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
     let json = get_body_json(HttpTransaction::Req(req)).await?;
@@ -57,24 +100,155 @@ async fn punish(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infall
     };
     let reason = json["reason"].as_str().ok_or(BackendError::new("reason missing", 400))?;
     let alsoip = json["alsoip"].as_bool().unwrap_or(false);
+    let ipv4_prefix = read_prefix(&json, "ipv4_prefix", DEFAULT_IPV4_PREFIX, IPV4_PREFIXES)?;
+    let ipv6_prefix = read_prefix(&json, "ipv6_prefix", DEFAULT_IPV6_PREFIX, IPV6_PREFIXES)?;
     let allow_chat = json["allow_chat"].as_bool().unwrap_or(false);
     let allow_ranked = json["allow_ranked"].as_bool().unwrap_or(false);
     let allow_unranked = json["allow_unranked"].as_bool().unwrap_or(false);
     let allow_join_minigames = json["allow_join_minigames"].as_bool().unwrap_or(false);
-
-    let pun = create_punishment(user_uuid, title, r#type, creation_date, expiration_date, reason, alsoip, allow_chat, allow_ranked, allow_unranked, allow_join_minigames)?;
+    let issuer = json["issuer"].as_str();
+
+    let pun = create_punishment(user_uuid, Punishment {
+        id: 0, title: title.into(),
+        r#type: r#type.into(),
+        creation_date, expiration_date,
+        reason: reason.into(),
+        alsoip, subnet: None, allow_chat, allow_ranked,
+        allow_unranked, allow_join_minigames,
+        issuer: issuer.map(|i| i.into()),
+        revoked_by: None, revoked_at: None, revoke_reason: None,
+        template: None
+    }, ipv4_prefix, ipv6_prefix)?;
     Ok(response_json(pun.to_json()))
 }
 
+/**
+* Punish a player using a template from punishment_templates.json, the step of the template's
+* ladder is picked from how many times the player has already been punished with it.
+*
+* Expects: body {
+*   uuid: string,
+*   template: string,
+*   issuer: string (optional),
+*   reason: string (optional, overrides the template's reason)
+* }
+*/
+async fn punish_template(req: Request<Incoming>, templates: Arc<std::sync::Mutex<PunishmentTemplates>>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+
+    let uuid = json["uuid"].as_str().ok_or(BackendError::new("uuid missing", 400))?;
+    let name = json["template"].as_str().ok_or(BackendError::new("template missing", 400))?;
+    let issuer = json["issuer"].as_str();
+    let reason = json["reason"].as_str();
+
+    if !user_exists(uuid)? {
+        return Err(BackendError::new("User not found", 404));
+    }
+
+    let offences = count_template_offences(uuid, name)?;
+    let (punishment, ipv4_prefix, ipv6_prefix) = {
+        let templates = templates.lock().unwrap();
+        let template = templates.get(name).ok_or(BackendError::new("Template not found", 404))?;
+        let step = template.get_step(offences).ok_or(BackendError::new("Template has no steps", 500))?;
+
+        (template.build(step, issuer, reason, Utc::now()), step.ipv4_prefix, step.ipv6_prefix)
+    };
+
+    let pun = create_punishment(uuid, punishment, ipv4_prefix, ipv6_prefix)?;
+
+    Ok(response_json(object! {
+        offence: offences + 1,
+        punishment: pun.to_json()
+    }))
+}
+
+async fn get_punishment_templates(_: Request<Incoming>, templates: Arc<std::sync::Mutex<PunishmentTemplates>>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let templates = templates.lock().unwrap();
+
+    Ok(response_json(templates.to_json()))
+}
+
+/**
+* Revoke a punishment of a player given by uuid or username, the original expiration date is
+* kept and the revocation is recorded in the punishment and the audit log.
+*/
 async fn unpunish(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
     let json = get_body_json(HttpTransaction::Req(req)).await?;
 
-    let username = json["username"].as_str().ok_or(BackendError::new("username missing", 400))?;
     let pun_id = json["punishment_id"].as_u64().ok_or(BackendError::new("punishment_id missing", 400))?;
+    let actor = json["actor"].as_str();
+    let reason = json["reason"].as_str().unwrap_or("Unspecified");
 
-    unpunish_by_name(username, pun_id)?;
+    let pun = if let Some(uuid) = json["uuid"].as_str() {
+        revoke_punishment(uuid, pun_id, actor, reason)?
+    } else {
+        let username = json["username"].as_str().ok_or(BackendError::new("uuid or username missing", 400))?;
+        revoke_punishment_by_name(username, pun_id, actor, reason)?
+    };
 
-    Ok(response_json(object! { ok: true }))
+    Ok(response_json(pun.to_json()))
+}
+
+/**
+* Query the moderation audit log, newest first. Every param is optional: target, actor,
+* action (issue/revoke), punishment_id, before (an entry id, for paging) and limit.
+*/
+async fn audit(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_optional_url_args(&req)?;
+
+    let action = match args.get("action") {
+        Some(a) => Some(AuditAction::try_from(a.as_ref())?),
+        None => None
+    };
+    let punishment_id = args.get("punishment_id").map(|id| id.parse::<u64>()).transpose().map_err(|_| BackendError::new("punishment_id invalid", 400))?;
+    let before = args.get("before").map(|id| id.parse::<u64>()).transpose().map_err(|_| BackendError::new("before invalid", 400))?;
+    let limit = args.get("limit").map(|l| l.parse::<usize>()).transpose().map_err(|_| BackendError::new("limit invalid", 400))?.unwrap_or(50).min(500);
+
+    let entries = query_audit(&AuditFilter {
+        target: args.get("target").map(|t| t.as_ref()),
+        actor: args.get("actor").map(|a| a.as_ref()),
+        action, punishment_id, before, limit
+    })?;
+
+    Ok(response_json(object! {
+        entries: JsonValue::Array(entries.iter().map(|e| e.to_json()).collect()),
+        next: entries.last().filter(|_| entries.len() == limit).map(|e| e.id)
+    }))
+}
+
+/**
+* Search every punishment, newest first, for moderation dashboards. Every param is optional:
+* type, active (true/false), reason (substring), issuer, cursor (the next_cursor of the previous
+* page) and limit.
+*/
+async fn search_punishments(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_optional_url_args(&req)?;
+
+    let active = match args.get("active").map(|a| a.as_ref()) {
+        Some("true") => Some(true),
+        Some("false") => Some(false),
+        Some(_) => return Err(BackendError::new("active must be true or false", 400)),
+        None => None
+    };
+    let limit = args.get("limit").map(|l| l.parse::<usize>()).transpose().map_err(|_| BackendError::new("limit invalid", 400))?.unwrap_or(50).clamp(1, 500);
+
+    let (puns, next) = query_punishments(&PunishmentFilter {
+        r#type: args.get("type").map(|t| t.as_ref()),
+        active,
+        reason: args.get("reason").map(|r| r.as_ref()),
+        issuer: args.get("issuer").map(|i| i.as_ref()),
+        cursor: args.get("cursor").map(|c| c.as_ref()),
+        limit
+    })?;
+
+    Ok(response_json(object! {
+        punishments: JsonValue::Array(puns.iter().map(|(pun, owner)| {
+            let mut json = pun.to_json();
+            json["target"] = owner.as_deref().into();
+            json
+        }).collect()),
+        next_cursor: next.as_deref()
+    }))
 }
 
 /**
@@ -97,11 +271,117 @@ async fn user_connected(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes
     let name = args.get(&Into::<Box<str>>::into("name")).ok_or(BackendError::new("Falformed url, uuid expected", 400))?;
     let address = args.get(&Into::<Box<str>>::into("address")).ok_or(BackendError::new("Falformed url, address expected", 400))?;
 
-    let data = get_user_connected(uuid.as_ref(), name.as_ref(), address.as_ref())?;
+    let (data, evaded) = get_user_connected(uuid.as_ref(), name.as_ref(), address.as_ref())?;
+    let mut json = data.to_json();
+    json["restrictions"] = Restrictions::evaluate(&data.punishments, Utc::now()).to_json();
+    json["ban_evasion"] = (!evaded.is_empty()).into();
+    json["evaded_punishments"] = evaded.into();
 
-    Ok(response_json(data.to_json()))
+    Ok(response_json(json))
+}
+
+/**
+* Check a single permission of a player, resolved with wildcards, negation and user over group
+* precedence, so every server enforces the same rules. The optional context param
+* ("server=survival,world=nether", percent-encoded or not) selects which scoped nodes and
+* memberships apply.
+*
+* Returns: { uuid, perm, value, node, contexts, source }
+*/
+async fn has_permission(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let uuid = args.get("uuid").ok_or(BackendError::new("Malformed url, uuid param is required", 400))?;
+    let perm = args.get("perm").ok_or(BackendError::new("Malformed url, perm param is required", 400))?;
+
+    let contexts: Contexts = args.get("context").map(|c| c.parse()).transpose()?.unwrap_or_default();
+
+    let holder = get_permission_holder(uuid)?.ok_or(BackendError::new("User not found", 404))?;
+    let mut json = holder.check(perm, &contexts).to_json();
+    json["uuid"] = uuid.as_ref().into();
+    json["perm"] = perm.as_ref().into();
+
+    Ok(response_json(json))
+}
+
+/**
+* Resolve many permissions for many players at once.
+*
+* Expects: body {
+*   uuids: [string],
+*   perms: [string],
+*   context: string (optional, "server=survival,world=nether")
+* }
+* Returns: { results: { <uuid>: { <perm>: bool } } }, unknown players are null.
+*/
+async fn resolve_permissions(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+    if !json["uuids"].is_array() || !json["perms"].is_array() {
+        return Err(BackendError::new("uuids and perms must be arrays", 400));
+    }
+
+    let perms: Vec<&str> = json["perms"].members().filter_map(|p| p.as_str()).collect();
+    let contexts: Contexts = json["context"].as_str().unwrap_or("").parse()?;
+    let mut results = JsonValue::new_object();
+
+    for uuid in json["uuids"].members().filter_map(|u| u.as_str()) {
+        results[uuid] = match get_permission_holder(uuid)? {
+            Some(holder) => {
+                let mut resolved = JsonValue::new_object();
+                for perm in &perms {
+                    resolved[*perm] = holder.check(perm, &contexts).value.into();
+                }
+                resolved
+            },
+            None => JsonValue::Null
+        };
+    }
+
+    Ok(response_json(object! { results: results }))
+}
+
+/**
+* Get what a player is currently allowed to do, computed from their active punishments and the
+* ones matching their last known address.
+*/
+async fn restrictions(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let uuid = args.get(&Into::<Box<str>>::into("uuid")).ok_or(BackendError::new("Malformed url, uuid expected", 400))?;
+
+    if !user_exists(uuid)? {
+        return Err(BackendError::new("User not found", 404));
+    }
+
+    let puns = get_effective_punishments(uuid)?;
+    let mut json = Restrictions::evaluate(&puns, Utc::now()).to_json();
+    json["uuid"] = uuid.as_ref().into();
+
+    Ok(response_json(json))
+}
+
+/**
+* List the accounts that share ips with a player, with how many addresses they share and their
+* active punishments.
+*/
+async fn alts(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let uuid = args.get(&Into::<Box<str>>::into("uuid")).ok_or(BackendError::new("Malformed url, uuid expected", 400))?;
+
+    if !user_exists(uuid)? {
+        return Err(BackendError::new("User not found", 404));
+    }
+
+    Ok(response_json(object! {
+        uuid: uuid.as_ref(),
+        alts: JsonValue::Array(get_alts(uuid)?.iter().map(|a| a.to_json()).collect())
+    }))
 }
 
+/**
+* Saves a cached user. The backend owns some of its fields, they are ignored here:
+* perms are changed with add_perm_to_user and remove_perm_from_user, groups with
+* set_user_group, add_user_group and remove_user_group, the inbox with mail_send and
+* mail_update, and only punishments the backend doesn't know yet are stored.
+*/
 async fn user_save(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
     let json = get_body_json(HttpTransaction::Req(req)).await?;
 
@@ -133,12 +413,37 @@ async fn get_group(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Inf
     }
 }
 
+/**
+* Optional expires_at (millis) of timed grants, it must be in the future.
+*/
+fn get_expires_at(json: &JsonValue) -> Result<Option<DateTime<Utc>>, BackendError> {
+    let Some(millis) = json["expires_at"].as_i64() else {
+        return Ok(None);
+    };
+    let expires_at = DateTime::from_timestamp_millis(millis).ok_or(BackendError::new("expires_at invalid", 400))?;
+    if expires_at <= Utc::now() {
+        return Err(BackendError::new("expires_at is in the past", 400));
+    }
+
+    Ok(Some(expires_at))
+}
+
+/**
+* Optional contexts of scoped grants, as "server=survival,world=nether".
+*/
+fn get_contexts(json: &JsonValue) -> Result<Contexts, BackendError> {
+    json["contexts"].as_str().unwrap_or("").parse()
+}
+
 async fn set_user_group_by_name(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
     let json = get_body_json(HttpTransaction::Req(req)).await?;
     let username = json["username"].as_str().ok_or(BackendError::new("username missing", 400))?;
     let group_name = json["group"].as_str().ok_or(BackendError::new("group missing", 400))?;
 
-    set_group_to_user_by_name(username, group_name)?;
+    let contexts = get_contexts(&json)?;
+    let expires_at = get_expires_at(&json)?;
+
+    set_group_to_user_by_name(username, group_name, &contexts, expires_at)?;
 
     Ok(response_json(object! { ok: true }))
 }
@@ -147,12 +452,25 @@ async fn delete_perms_and_update_group(req: Request<Incoming>) -> Result<Respons
     let json = get_body_json(HttpTransaction::Req(req)).await?;
     let group = Group::from_json(&json)?;
 
+    check_group_parents(&group)?;
     remove_perms_from_group(&group)?;
     put_group(&group)?;
 
     Ok(response_json(object! { ok: true }))
 }
 
+/**
+* Create or update a group.
+*
+* Expects: body {
+*   name: string,
+*   prefix: string,
+*   suffix: string,
+*   perms: [{ perm: string, value: bool, contexts: string (optional) }],
+*   parents: [string] (optional, replaces the current parents),
+*   weight: i32 (optional, default 0)
+* }
+*/
 async fn update_group(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
     let json = get_body_json(HttpTransaction::Req(req)).await?;
     let group = Group::from_json(&json)?;
@@ -176,12 +494,54 @@ async fn remove_perm_from_group(req: Request<Incoming>) -> Result<Response<BoxBo
     let json = get_body_json(HttpTransaction::Req(req)).await?;
     let group_name = json["group_name"].as_str().ok_or(BackendError::new("group_name missing", 400))?;
     let perm = json["permission"].as_str().ok_or(BackendError::new("permission missing", 400))?;
+    let contexts = get_contexts(&json)?;
+
+    delete_permission_from_group(group_name, perm, &contexts)?;
+
+    Ok(response_json(object! { ok: true }))
+}
+
+/**
+* Grant a permission to a user without saving the whole user.
+*
+* Expects: body {
+*   uuid: string,
+*   perm: { perm: string, value: bool, contexts: string (optional), expires_at: millis (optional) }
+* }
+*/
+async fn add_perm_to_user(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+    let uuid = json["uuid"].as_str().ok_or(BackendError::new("uuid missing", 400))?;
+    let mut perm = Permission::from_json(&json["perm"])?;
+    perm.expires_at = get_expires_at(&json["perm"])?;
+
+    put_permission_to_user(uuid, &perm)?;
+
+    Ok(response_json(object! { ok: true }))
+}
+
+async fn remove_perm_from_user(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+    let uuid = json["uuid"].as_str().ok_or(BackendError::new("uuid missing", 400))?;
+    let perm = json["permission"].as_str().ok_or(BackendError::new("permission missing", 400))?;
+    let contexts = get_contexts(&json)?;
 
-    delete_permission_from_group(group_name, perm)?;
+    delete_permission_from_user(uuid, perm, &contexts)?;
 
     Ok(response_json(object! { ok: true }))
 }
 
+async fn get_user_perms(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let uuid = args.get("uuid").ok_or(BackendError::new("Malformed url, uuid param is required", 400))?;
+
+    let perms = get_permissions_of_user(uuid)?.ok_or(BackendError::new("User not found", 404))?;
+
+    Ok(response_json(object! {
+        perms: JsonValue::Array(perms.iter().map(|p| p.to_json()).collect())
+    }))
+}
+
 async fn delete_group(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
     let json = get_body_json(HttpTransaction::Req(req)).await?;
     let group_name = json["name"].as_str().ok_or(BackendError::new("name missing", 400))?;
@@ -198,7 +558,39 @@ async fn set_user_group(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes
     let uuid = json["uuid"].as_str().ok_or(BackendError::new("uuid missing", 400))?;
     let group_name = json["group"].as_str().ok_or(BackendError::new("group missing", 400))?;
 
-    set_group_to_user(uuid, group_name)?;
+    let contexts = get_contexts(&json)?;
+    let expires_at = get_expires_at(&json)?;
+
+    set_group_to_user(uuid, group_name, &contexts, expires_at)?;
+
+    Ok(response_json(object! { ok: true }))
+}
+
+/**
+* Give a group to a user without removing the ones they already hold, expires_at (millis,
+* optional) makes the membership temporary and contexts (optional) limits where it applies.
+*/
+async fn add_user_group(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+    let uuid = json["uuid"].as_str().ok_or(BackendError::new("uuid missing", 400))?;
+    let group_name = json["group"].as_str().ok_or(BackendError::new("group missing", 400))?;
+
+    let contexts = get_contexts(&json)?;
+    let expires_at = get_expires_at(&json)?;
+
+    add_group_to_user(uuid, group_name, &contexts, expires_at)?;
+
+    Ok(response_json(object! { ok: true }))
+}
+
+async fn remove_user_group(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+    let uuid = json["uuid"].as_str().ok_or(BackendError::new("uuid missing", 400))?;
+    let group_name = json["group"].as_str().ok_or(BackendError::new("group missing", 400))?;
+
+    let contexts = get_contexts(&json)?;
+
+    remove_group_from_user(uuid, group_name, &contexts)?;
 
     Ok(response_json(object! { ok: true }))
 }
@@ -222,112 +614,274 @@ async fn user_friend_remove(req: Request<Incoming>) -> Result<Response<BoxBody<B
     Ok(response_json(object! { ok: true }))
 }
 
-const PROPAGATE: u8 = 0;
-const TARGET: u8 = 1;
-const CACHE_READ: u8 = 2;
-const CACHE_WRITE: u8 = 3;
-const CACHE_DELETE: u8 = 4;
-
-pub const REGULAR_MESSAGE: u8 = 0;
-pub const CACHE_RESPONSE: u8 = 1;
-
-async fn process_msg_bytes(
-    mut b: tungstenite::Bytes,
-    clients: Arc<Mutex<HashMap<Box<str>,
-    UnboundedSender<Message>>>>,
-    cache: Arc<Mutex<HashMap<i32, (Option<JoinHandle<()>>, CacheData)>>>,
-    sender: UnboundedSender<Message>
-) -> Result<(), BackendError> {
-    let packet_type = b.get_u8();
-    let source = read_prefixed_string(&mut b)?;
-
-    match packet_type {
-        PROPAGATE => {
-            let safe = clients.lock().await;
-            let source = source.into_boxed_str();
-            for c in safe.iter() {
-                let (key, client) = c;
-                if *key != source {
-                    client.send(encode_msg(&source, &mut b)?).map_err(|e| BackendError::new(&e.to_string(), 500))?;
-                }
-            }
+const GRANT_EXPIRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/**
+* Periodically removes expired group memberships and user permissions, removing them publishes
+* the invalidation events.
+*/
+async fn expire_grants() {
+    let mut interval = tokio::time::interval(GRANT_EXPIRY_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = remove_expired_grants(Utc::now()) {
+            eprintln!("Failed to remove expired grants: {}", e.get_msg());
+        }
+    }
+}
+
+const CACHE_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+* Periodically removes expired cache entries, reads already skip them in between.
+*/
+async fn expire_cache() {
+    let mut interval = tokio::time::interval(CACHE_EXPIRY_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = remove_expired_entries(Utc::now()).await {
+            eprintln!("Failed to remove expired cache entries: {}", e.get_msg());
+        }
+    }
+}
+
+/**
+* Forwards every invalidation event to the connected clients as an INVALIDATION packet, the
+* payload is the event as json ({ event, ... }).
+*/
+async fn forward_events() {
+    let mut events = subscribe();
+
+    loop {
+        match events.recv().await {
+            Ok(event) => broadcast(&ServerPacket::Invalidation(event.to_json())).await,
+            Err(RecvError::Lagged(n)) => eprintln!("Dropped {n} invalidation events, the websocket broadcast is lagging"),
+            Err(RecvError::Closed) => break
+        }
+    }
+}
+
+fn send_packet(client: &OutboundQueue, packet: &ServerPacket) -> Result<(), BackendError> {
+    client.send(Message::Binary(packet.encode()))
+}
+
+/**
+* Copies the queues of the connected clients matching filter, so packets can be sent without
+* holding the clients lock.
+*/
+async fn get_queues(filter: impl Fn(&str) -> bool) -> Vec<(Box<str>, OutboundQueue)> {
+    let safe = CLIENTS.lock().await;
+
+    safe.iter()
+        .filter(|(name, _)| filter(name))
+        .map(|(name, session)| (name.clone(), session.queue.clone()))
+        .collect()
+}
+
+/**
+* Sends an already encoded packet to each queue, a full or closed queue doesn't stop the rest.
+*/
+fn fan_out(queues: &[(Box<str>, OutboundQueue)], frame: &Bytes) {
+    for (name, queue) in queues {
+        if let Err(e) = queue.send(Message::Binary(frame.clone())) {
+            eprintln!("Failed to send packet to {name}: {}", e.get_msg());
+        }
+    }
+}
+
+/**
+* Sends a packet to every connected websocket client.
+*/
+pub async fn broadcast(packet: &ServerPacket) {
+    fan_out(&get_queues(|_| true).await, &packet.encode());
+}
+
+/**
+* Sends a packet to every client with a subscription matching the channel.
+*/
+pub async fn send_to_subscribers(channel: &str, packet: &ServerPacket) {
+    let subscribers = get_subscribers(channel).await;
+
+    fan_out(&get_queues(|name| subscribers.iter().any(|s| s.as_ref() == name)).await, &packet.encode());
+}
+
+/**
+* Sends a packet to the client connected as name, 404 if it isn't connected.
+*/
+pub async fn send_to(name: &str, packet: &ServerPacket) -> Result<(), BackendError> {
+    let queue = CLIENTS.lock().await.get(name).map(|session| session.queue.clone())
+        .ok_or(BackendError::new(&format!("{name} isn't connected"), 404))?;
+
+    send_packet(&queue, packet)
+}
+
+/**
+* Queue depth and counters of every connected websocket client.
+*/
+async fn ws_metrics(_: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let safe = CLIENTS.lock().await;
+    let clients = safe.iter().map(|(name, session)| {
+        let mut json = session.queue.metrics_json();
+
+        json["name"] = name.as_ref().into();
+        json["session"] = session.id.into();
+        json
+    }).collect();
+
+    Ok(response_json(JsonValue::Array(clients)))
+}
+
+/**
+* Handles a packet from an already registered client, source is the name it connected with.
+*/
+async fn process_packet(
+    packet: ClientPacket,
+    source: &str,
+    sender: &OutboundQueue
+) -> Result<(), PacketError> {
+    let clients = CLIENTS.clone();
+
+    match packet {
+        ClientPacket::Hello { .. } => return Err(PacketError::new(ErrorCode::Malformed, "Handshake already completed")),
+        ClientPacket::Propagate { payload } => {
+            let frame = ServerPacket::Message { source: source.into(), payload }.encode();
+
+            fan_out(&get_queues(|name| name != source).await, &frame);
         },
-        TARGET => {
-            let safe = clients.lock().await;
-            let name = read_prefixed_string(&mut b)?.into_boxed_str();
-            if let Some(client) = safe.get(&name) {
-                client.send(encode_msg(&source, &mut b)?).map_err(|e| BackendError::new(&e.to_string(), 500))?;
+        ClientPacket::Target { target, payload } => {
+            let queue = clients.lock().await.get(&target).map(|s| s.queue.clone());
+
+            if let Some(queue) = queue {
+                send_packet(&queue, &ServerPacket::Message { source: source.into(), payload })?;
             }
         },
-        CACHE_READ => {
-            let cache_id = b.get_i32();
-            let channel = read_prefixed_string(&mut b)?.into_boxed_str();
-            let map = cache.lock().await;
-            let mut response: BytesMut;
-
-            if let Some(cache) = map.get(&cache_id) && cache.1.channel == channel {
-                response = BytesMut::with_capacity(6 + cache.1.payload.len());
-                response.put_u8(CACHE_RESPONSE);
-                response.put_i32(cache_id);
-                response.put_u8(1); // true
-                response.extend_from_slice(&cache.1.payload);
-            } else {
-                response = BytesMut::with_capacity(6);
-                response.put_u8(CACHE_RESPONSE);
-                response.put_i32(cache_id);
-                response.put_u8(0); // false
-            }
+        ClientPacket::CacheRead { channel, key } => {
+            let payload = read_entry(&channel, &key).await?;
 
-            sender.send(Message::Binary(response.freeze())).map_err(|e| BackendError::new(&e.to_string(), 500))?;
+            send_packet(sender, &ServerPacket::CacheResponse { channel, key, payload })?;
         },
-        CACHE_WRITE => {
-            let cache_id = b.get_i32();
-            let expiration_millis = b.get_i64();
-            let channel = read_prefixed_string(&mut b)?;
-            let mut data = BytesMut::with_capacity(32);
-            data.extend_from_slice(&b);
-
-            let source = source.into_boxed_str();
-            let source_cp = source.clone();
-
-            if expiration_millis > 0 {
-                let cache_cl = cache.clone();
-                let handle = tokio::spawn(async move {
-                    tokio::time::sleep(Duration::from_millis(expiration_millis as u64)).await;
-
-                    let mut map = cache_cl.lock().await;
-                    if let Some(entry) = map.get(&cache_id) && entry.1.owner == source_cp {
-                        map.remove(&cache_id);
-                    }
-                });
-
-                let mut map = cache.lock().await;
-                map.insert(cache_id, (Some(handle), CacheData { payload: data.freeze(), owner: source, channel: channel.into_boxed_str() }));
-            } else {
-                let mut map = cache.lock().await;
-                map.insert(cache_id, (None, CacheData { payload: data.freeze(), owner: source, channel: channel.into_boxed_str() }));
+        ClientPacket::CacheWrite { channel, key, ttl_millis, flags, payload } => write_entry(source, &channel, &key, ttl_millis, flags, payload).await?,
+        ClientPacket::CacheDelete { channel, key } => delete_entry(source, &channel, &key).await?,
+        ClientPacket::Request { request_id, target, timeout_millis, payload } => {
+            let queue = clients.lock().await.get(&target).map(|s| s.queue.clone());
+            let Some(queue) = queue else {
+                reply(sender, request_id, RpcStatus::NotConnected, Bytes::new());
+                return Ok(());
+            };
+
+            let id = open_request(source, request_id, &target, timeout_millis, sender.clone()).await;
+            if send_packet(&queue, &ServerPacket::Request { request_id: id, source: source.into(), payload }).is_err() {
+                close_request(id, &target).await;
+                reply(sender, request_id, RpcStatus::NotConnected, Bytes::new());
             }
         },
-        CACHE_DELETE => {
-            let cache_id = b.get_i32();
-            let mut map = cache.lock().await;
-            if let Some(entry) = map.get(&cache_id) && entry.1.owner == source.into_boxed_str() {
-                if let Some(handle) = &entry.0 {
-                    handle.abort();
-                }
-                map.remove(&cache_id);
+        ClientPacket::Response { request_id, payload } => {
+            if let Some((requester, request_id)) = close_request(request_id, source).await {
+                reply(&requester, request_id, RpcStatus::Ok, payload);
             }
         },
-        _ => {}
+        ClientPacket::Subscribe { pattern } => add_subscription(source, &pattern).await?,
+        ClientPacket::Unsubscribe { pattern } => remove_subscription(source, &pattern).await,
+        ClientPacket::Publish { channel, payload } => {
+            validate_channel(&channel, false)?;
+
+            let subscribers = get_subscribers(&channel).await;
+            let frame = ServerPacket::ChannelMessage { channel, source: source.into(), payload }.encode();
+            let queues = get_queues(|name| name != source && subscribers.iter().any(|s| s.as_ref() == name)).await;
+
+            fan_out(&queues, &frame);
+        },
+        ClientPacket::RegisterServer(metadata) => register_server(source, metadata).await?,
+        ClientPacket::ServerStatus { players, tps } => update_server_status(source, players, tps).await?,
+        ClientPacket::ListServers { r#type } => {
+            let servers = list_servers(Some(r#type.as_ref()).filter(|t| !t.is_empty()), None).await;
+
+            send_packet(sender, &ServerPacket::ServerList(JsonValue::Array(servers.iter().map(|s| s.to_json()).collect())))?;
+        },
+        ClientPacket::PlayerJoin { uuid, name } => player_joined(source, &uuid, &name).await,
+        ClientPacket::PlayerQuit { uuid } => player_quit(source, &uuid).await
     };
+
     Ok(())
 }
 
+/**
+* Drops everything a session left behind: its cache entries, pending requests, subscriptions,
+* registry entry and the presence of its players.
+*/
+async fn cleanup_session(name: &str) {
+    drop_owned_entries(name).await;
+    fail_requests(name).await;
+    remove_subscriptions(name).await;
+    unregister_server(name).await;
+    clear_server(name).await;
+}
+
+/**
+* Checks the client's HELLO and registers the session under name, anything else than a HELLO
+* with the current protocol version is refused with an ERROR packet. With takeover a stale
+* session with the same name is kicked and cleaned up before this one starts.
+*/
+async fn handshake(frame: Message, name: &str, session: Session, takeover: bool) -> Result<(), PacketError> {
+    let Message::Binary(frame) = frame else {
+        return Err(PacketError::new(ErrorCode::HandshakeRequired, "Expected a HELLO packet"));
+    };
+
+    match ClientPacket::decode(frame) {
+        Ok(ClientPacket::Hello { version }) if version == PROTOCOL_VERSION => {},
+        Ok(ClientPacket::Hello { version }) => {
+            return Err(PacketError::new(ErrorCode::UnsupportedVersion, &format!("Unsupported protocol version {version}, expected {PROTOCOL_VERSION}")));
+        },
+        _ => return Err(PacketError::new(ErrorCode::HandshakeRequired, "Expected a HELLO packet"))
+    }
+
+    let sender = session.queue.clone();
+    let mut safe = CLIENTS.lock().await;
+    if !can_connect(&safe, name, takeover) {
+        return Err(PacketError::new(ErrorCode::NameInUse, "A client with that name is already connected"));
+    }
+    let replaced = safe.insert(name.into(), session);
+    drop(safe);
+
+    if let Some(old) = replaced {
+        println!("Websocket client {name} reconnected, replacing its previous session");
+        let _ = send_packet(&old.queue, &PacketError::new(ErrorCode::SessionReplaced, "Another session connected with this name").into());
+        let _ = old.queue.send(Message::Close(None));
+        old.kick.cancel();
+        cleanup_session(name).await;
+    }
+
+    send_packet(&sender, &ServerPacket::Welcome { version: PROTOCOL_VERSION, name: name.into() })?;
+    Ok(())
+}
+
+/**
+* Checks a packet against the sender's identity, it's looked up for every packet so removing
+* an identity or changing its restrictions applies right away.
+*/
+fn check_identity(identities: &std::sync::Mutex<WsIdentities>, name: &str, packet: &ClientPacket) -> Result<(), PacketError> {
+    match identities.lock().unwrap().get(name) {
+        Some(identity) => identity.check(packet),
+        None => Err(PacketError::new(ErrorCode::Forbidden, "Identity revoked"))
+    }
+}
+
+/**
+* Upgrades to a websocket for game servers, name must be one of the identities in
+* ws_identities.json and the X-Client-Secret header its secret. The first frame must be a
+* HELLO, see docs/core-protocol.md.
+*
+* The backend pings the client periodically and evicts it after too many pings without any
+* frame back, see WsSettings.
+*/
 async fn create_ws(
     req: Request<Incoming>,
-    clients: Arc<Mutex<HashMap<Box<str>,
-    UnboundedSender<Message>>>>,
-    cache: Arc<Mutex<HashMap<i32, (Option<JoinHandle<()>>, CacheData)>>>
+    identities: Arc<std::sync::Mutex<WsIdentities>>,
+    settings: Arc<std::sync::Mutex<WsSettings>>
 ) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
     let mut query = get_body_url_args(&req)?;
     let name = query.remove(&Into::<Box<str>>::into("name"));
@@ -337,93 +891,169 @@ async fn create_ws(
     }
 
     let name = name.unwrap();
+    let secret = req.headers().get("x-client-secret").and_then(|s| s.to_str().ok()).unwrap_or("");
 
-    let (res, websocket) = hyper_tungstenite::upgrade(req, Some(WebSocketConfig::default()))?;
-    let mut safe = clients.lock().await;
-    let (tx, mut rx) = unbounded_channel();
-    if safe.contains_key(&name.clone()) {
-        return Err(BackendError::new("A client with that name already exists", 400));
+    if !identities.lock().unwrap().get(&name).is_some_and(|i| i.verify(secret)) {
+        return Err(BackendError::new("Invalid client name or secret", 401));
     }
-    safe.insert(name.clone(), tx.clone());
 
-    drop(safe);
+    let settings = settings.lock().unwrap().clone();
+    if !can_connect(&*CLIENTS.lock().await, &name, settings.takeover) {
+        return Err(BackendError::new("A client with that name is already connected", 400));
+    }
+
+    let (res, websocket) = hyper_tungstenite::upgrade(req, Some(WebSocketConfig::default()))?;
+    let kick = CancellationToken::new();
+    let (tx, mut rx) = OutboundQueue::new(settings.queue_capacity, settings.overflow_policy, kick.clone());
 
     tokio::spawn(async move {
         if let Ok(ws) = websocket.await {
             let (mut writer, mut reader) = ws.split();
-            
-            // Send messages safely
+
+            // Send messages safely, the writer stops after a close frame
             tokio::spawn(async move {
                 while let Some(msg) = rx.recv().await {
+                    let close = matches!(msg, Message::Close(_));
+
                     if let Err(e) = writer.send(msg).await {
-                        println!("Error sending websocket msg: {}", e.to_string());
+                        eprintln!("Error sending websocket msg: {e}");
+                    }
+                    if close {
+                        break;
                     }
                 }
             });
 
+            let Ok(Some(Ok(first))) = tokio::time::timeout(settings.handshake_timeout, reader.next()).await else {
+                eprintln!("Websocket client {} didn't complete the handshake", name);
+                let _ = tx.send(Message::Close(None));
+                return;
+            };
+
+            let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+            let missed_pongs = Arc::new(AtomicU32::new(0));
+            let session = Session {
+                id, queue: tx.clone(), kick: kick.clone(), missed_pongs: missed_pongs.clone(), max_missed_pongs: settings.max_missed_pongs
+            };
+
+            if let Err(e) = handshake(first, &name, session, settings.takeover).await {
+                eprintln!("Websocket handshake with {} failed: {}", name, e);
+                let _ = send_packet(&tx, &e.into());
+                let _ = tx.send(Message::Close(None));
+                return;
+            }
+
+            let mut heartbeat = tokio::time::interval(settings.ping_interval);
+            heartbeat.tick().await;
+
             // Listen for messages
-            while let Some(msg) = reader.next().await {
-                match msg {
-                    Ok(Message::Binary(b)) => {
-                        if let Err(e) = process_msg_bytes(b, clients.clone(), cache.clone(), tx.clone()).await {
-                            eprintln!("Failed to process websocket packet from {}: {}", name, e.get_msg());
+            loop {
+                let msg = tokio::select! {
+                    _ = kick.cancelled() => break,
+                    _ = heartbeat.tick() => {
+                        let missed = missed_pongs.load(Ordering::Relaxed);
+                        if missed >= settings.max_missed_pongs {
+                            eprintln!("Evicting websocket client {}, it missed {} pings", name, missed);
+                            let _ = tx.send(Message::Close(None));
+                            break;
                         }
+                        missed_pongs.fetch_add(1, Ordering::Relaxed);
+                        let _ = tx.send(Message::Ping(Bytes::new()));
+                        continue;
                     },
-                    Ok(Message::Close(_)) => {
-                        drop(reader);
-                        break;
+                    msg = reader.next() => msg
+                };
+                let Some(msg) = msg else { break };
+                missed_pongs.store(0, Ordering::Relaxed);
+
+                let result = match msg {
+                    Ok(Message::Binary(b)) => match ClientPacket::decode(b).and_then(|p| check_identity(&identities, &name, &p).map(|_| p)) {
+                        Ok(packet) => process_packet(packet, &name, &tx).await,
+                        Err(e) => Err(e)
                     },
+                    Ok(Message::Text(_)) => Err(PacketError::new(ErrorCode::Malformed, "Only binary frames are supported")),
+                    Ok(Message::Close(_)) => break,
                     Err(e) => {
                         eprintln!("A client ended a websocket abruptly: {}", e.to_string());
-                        drop(reader);
                         break;
                     },
-                    _ => {}
-                }
-            }
+                    _ => Ok(())
+                };
 
-            // Cleanup
-            let mut caches = cache.lock().await;
-            caches.retain(|_, data| {
-                let res = data.1.owner == name;
-                if res && let Some(handle) = &data.0 {
-                    handle.abort();
+                if let Err(e) = result {
+                    eprintln!("Failed to process websocket packet from {}: {}", name, e);
+                    if let Err(e) = send_packet(&tx, &e.into()) {
+                        eprintln!("Failed to send error packet to {}: {}", name, e.get_msg());
+                    }
                 }
-                res
-            });
-            drop(caches);
-
-            let mut safe = clients.lock().await;
-            safe.remove(&name);
+            }
+            drop(reader);
+
+            // Cleanup, unless another session took over the name
+            let mut safe = CLIENTS.lock().await;
+            if safe.get(&name).is_some_and(|s| s.id == id) {
+                safe.remove(&name);
+                drop(safe);
+                cleanup_session(&name).await;
+            }
         }
     });
 
     Ok(res.map(|b| BoxBody::new(b)))
 }
 
-pub async fn register(node: &mut Node) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let clients = Arc::new(Mutex::new(HashMap::new()));
-    let bytes = Arc::new(Mutex::new(HashMap::new()));
+pub async fn register(node: &mut Node, watcher: &mut DirWatcher) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let templates = PunishmentTemplates::open("punishment_templates.json", watcher)?;
+    let templates_cl = templates.clone();
+    let identities = WsIdentities::open("ws_identities.json", watcher)?;
+    let ws_settings = WsSettings::open("websocket.json", watcher)?;
+
+    let core = node.subnode("/core")?;
 
-    node.subnode("/core")?
+    tokio::spawn(expire_grants());
+    tokio::spawn(forward_events());
+    tokio::spawn(expire_cache());
+
+    core
         .endpoint("/player_data", Method::Get, player_data)?
         .endpoint("/user_connected", Method::Get, user_connected)?
+        .endpoint("/alts", Method::Get, alts)?
+        .endpoint("/restrictions", Method::Get, restrictions)?
+        .endpoint("/has_permission", Method::Get, has_permission)?
+        .endpoint("/resolve_permissions", Method::Post, resolve_permissions)?
         .endpoint("/get_groups", Method::Get, get_groups)?
         .endpoint("/get_group", Method::Get, get_group)?
         .endpoint("/set_user_group", Method::Put, set_user_group)?
         .endpoint("/set_user_group_by_name", Method::Put, set_user_group_by_name)?
+        .endpoint("/add_user_group", Method::Put, add_user_group)?
+        .endpoint("/remove_user_group", Method::Delete, remove_user_group)?
         .endpoint("/update_group", Method::Post, update_group)?
         .endpoint("/delete_perms_and_update_group", Method::Put, delete_perms_and_update_group)?
         .endpoint("/add_perm_to_group", Method::Put, add_perm_to_group)?
         .endpoint("/remove_perm_from_group", Method::Delete, remove_perm_from_group)?
         .endpoint("/delete_group", Method::Delete, delete_group)?
+        .endpoint("/add_perm_to_user", Method::Put, add_perm_to_user)?
+        .endpoint("/remove_perm_from_user", Method::Delete, remove_perm_from_user)?
+        .endpoint("/get_user_perms", Method::Get, get_user_perms)?
         .endpoint("/punish", Method::Post, punish)?
+        .endpoint("/punish_template", Method::Post, move |req| punish_template(req, templates.clone()))?
+        .endpoint("/punishment_templates", Method::Get, move |req| get_punishment_templates(req, templates_cl.clone()))?
         .endpoint("/unpunish", Method::Put, unpunish)?
+        .endpoint("/audit", Method::Get, audit)?
+        .endpoint("/search_punishments", Method::Get, search_punishments)?
         .endpoint("/user_save", Method::Put, user_save)?
         .endpoint("/user_friend_remove", Method::Put, user_friend_remove)?
         .endpoint("/set_group_default", Method::Put, set_group_default)?
-        .endpoint("/create_ws", Method::Get, move |req| create_ws(req, clients.clone(), bytes.clone()))?
+        .endpoint("/ws_metrics", Method::Get, ws_metrics)?
+        .endpoint("/create_ws", Method::Get, move |req| create_ws(req, identities.clone(), ws_settings.clone()))?
         .middleware(privileged_middleware);
 
+    appeals::register_privileged(core)?;
+    reports::register_privileged(core)?;
+    notes::register_privileged(core)?;
+    presence::register_privileged(core)?;
+    messages::register_privileged(core)?;
+    cache::register_privileged(core)?;
+
     Ok(())
 }
@@ -0,0 +1,111 @@
+use std::{convert::Infallible, error::Error};
+
+use http_body_util::combinators::BoxBody;
+use hyper::{Request, Response, body::{Bytes, Incoming}};
+use json::object;
+
+use crate::api::{control::{messaging::{MAX_PRIVATE_MESSAGE_LENGTH, check_private_message}, presence::find_player, storage::query::{get_effective_punishments, get_user, push_mail, update_mail}}, routers::core::send_to, typedef::{BackendError, mailing::{get_mail_from_json, message::Message}, protocol::ServerPacket, routing::{Method, nodes::Node}}, utils::{HttpTransaction, get_body_json, response_json}};
+
+/**
+* Routes a private message to the recipient's current server, or to their inbox when they're
+* offline. Answers { status: "blocked", reason } when the recipient's settings or a mute of the
+* sender forbid it, { status: "delivered", server } or { status: "inbox" } otherwise.
+*
+* Expects: body {
+*   sender: string,
+*   recipient: string,
+*   message: string
+* }
+*/
+async fn private_message(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+
+    let sender = json["sender"].as_str().ok_or(BackendError::new("sender missing", 400))?;
+    let recipient = json["recipient"].as_str().ok_or(BackendError::new("recipient missing", 400))?;
+    let message = json["message"].as_str().ok_or(BackendError::new("message missing", 400))?;
+
+    if message.trim().is_empty() || message.chars().count() > MAX_PRIVATE_MESSAGE_LENGTH {
+        return Err(BackendError::new(&format!("message must be 1 to {MAX_PRIVATE_MESSAGE_LENGTH} characters"), 400));
+    }
+    if sender == recipient {
+        return Err(BackendError::new("Can't message yourself", 400));
+    }
+
+    let sender_user = get_user(sender)?.ok_or(BackendError::new("Sender not found", 404))?;
+    let recipient_user = get_user(recipient)?.ok_or(BackendError::new("Recipient not found", 404))?;
+    let presence = find_player(recipient).await;
+
+    if let Err(block) = check_private_message(&sender_user, &get_effective_punishments(sender)?, &recipient_user, presence.is_some()) {
+        return Ok(response_json(object! { status: "blocked", reason: block.as_str() }));
+    }
+
+    if let Some(presence) = presence {
+        let packet = ServerPacket::PrivateMessage {
+            sender: sender.into(),
+            sender_name: sender_user.name.clone(),
+            recipient: recipient.into(),
+            message: message.into()
+        };
+
+        match send_to(&presence.server, &packet).await {
+            Ok(()) => return Ok(response_json(object! { status: "delivered", server: presence.server.as_ref() })),
+            Err(e) => eprintln!("Failed to deliver a private message to {}: {}", presence.server, e.get_msg())
+        }
+    }
+
+    push_mail(recipient, &Message::new(&sender_user.name, message))?;
+
+    Ok(response_json(object! { status: "inbox" }))
+}
+
+/**
+* Puts a mail in the user's inbox and answers { id } with the id it was given.
+*
+* Expects: body {
+*   uuid: string,
+*   mail: { type: number, msg: string, sender: string, coins: number (coins only) }
+* }
+*/
+async fn mail_send(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+
+    let uuid = json["uuid"].as_str().ok_or(BackendError::new("uuid missing", 400))?;
+    let mail = get_mail_from_json(&json["mail"]).map_err(|e| BackendError::new(&e.to_string(), 400))?;
+
+    Ok(response_json(object! { id: push_mail(uuid, mail.as_ref())? }))
+}
+
+/**
+* Flags a mail of the user's inbox as deleted or claimed, absent flags are left as they are.
+*
+* Expects: body {
+*   uuid: string,
+*   id: number,
+*   deleted: bool (optional),
+*   claimed: bool (optional)
+* }
+*/
+async fn mail_update(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+
+    let uuid = json["uuid"].as_str().ok_or(BackendError::new("uuid missing", 400))?;
+    let id = json["id"].as_u64().ok_or(BackendError::new("id missing", 400))?;
+
+    if !update_mail(uuid, id, json["deleted"].as_bool(), json["claimed"].as_bool())? {
+        return Err(BackendError::new("Mail not found", 404));
+    }
+
+    Ok(response_json(object! { ok: true }))
+}
+
+/**
+* Private messaging and inbox mails, registered under the privileged core node.
+*/
+pub fn register_privileged(core: &mut Node) -> Result<(), Box<dyn Error + Send + Sync>> {
+    core
+        .endpoint("/private_message", Method::Post, private_message)?
+        .endpoint("/mail_send", Method::Post, mail_send)?
+        .endpoint("/mail_update", Method::Put, mail_update)?;
+
+    Ok(())
+}
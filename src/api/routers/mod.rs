@@ -6,6 +6,13 @@ pub mod state;
 pub mod stream;
 pub mod redirections;
 pub mod mods;
+pub mod appeals;
+pub mod reports;
+pub mod notes;
+pub mod servers;
+pub mod presence;
+pub mod messages;
+pub mod cache;
 
 use std::{convert::Infallible, sync::Arc};
 use hyper::{body::{Bytes, Incoming}, Request, Response};
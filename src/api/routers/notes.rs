@@ -0,0 +1,77 @@
+use std::{convert::Infallible, error::Error};
+
+use http_body_util::combinators::BoxBody;
+use hyper::{Request, Response, body::{Bytes, Incoming}};
+use json::{JsonValue, object};
+
+use crate::api::{control::storage::{notes::{add_note, delete_note, get_moderation_timeline, get_notes}, query::user_exists}, typedef::{BackendError, jsonutils::SerializableJson, routing::{Method, nodes::Node}}, utils::{HttpTransaction, get_body_json, get_body_url_args, response_json}};
+
+/**
+* Expects: body {
+*   uuid: string,
+*   author: string,
+*   text: string
+* }
+*/
+async fn add(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+
+    let uuid = json["uuid"].as_str().ok_or(BackendError::new("uuid missing", 400))?;
+    let author = json["author"].as_str().ok_or(BackendError::new("author missing", 400))?;
+    let text = json["text"].as_str().ok_or(BackendError::new("text missing", 400))?;
+
+    if !user_exists(uuid)? {
+        return Err(BackendError::new("User not found", 404));
+    }
+
+    Ok(response_json(add_note(uuid, author, text)?.to_json()))
+}
+
+async fn list(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let uuid = args.get("uuid").ok_or(BackendError::new("Malformed url, uuid param is required", 400))?;
+
+    Ok(response_json(object! {
+        notes: JsonValue::Array(get_notes(uuid)?.iter().map(|n| n.to_json()).collect())
+    }))
+}
+
+async fn delete(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let uuid = args.get("uuid").ok_or(BackendError::new("Malformed url, uuid param is required", 400))?;
+    let id = args.get("id").ok_or(BackendError::new("Malformed url, id param is required", 400))?
+        .parse::<u64>().map_err(|_| BackendError::new("id invalid", 400))?;
+
+    delete_note(uuid, id)?;
+
+    Ok(response_json(object! { ok: true }))
+}
+
+/**
+* Moderation history of a player, see get_moderation_timeline.
+*/
+async fn timeline(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let uuid = args.get("uuid").ok_or(BackendError::new("Malformed url, uuid param is required", 400))?;
+
+    if !user_exists(uuid)? {
+        return Err(BackendError::new("User not found", 404));
+    }
+
+    Ok(response_json(object! {
+        timeline: JsonValue::Array(get_moderation_timeline(uuid)?)
+    }))
+}
+
+/**
+* Staff endpoints, registered under the privileged core node.
+*/
+pub fn register_privileged(core: &mut Node) -> Result<(), Box<dyn Error + Send + Sync>> {
+    core.endpoint("/timeline", Method::Get, timeline)?;
+    core.subnode("/notes")?
+        .endpoint("/add", Method::Post, add)?
+        .endpoint("/list", Method::Get, list)?
+        .endpoint("/delete", Method::Delete, delete)?;
+
+    Ok(())
+}
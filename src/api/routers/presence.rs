@@ -0,0 +1,60 @@
+use std::{convert::Infallible, error::Error};
+
+use http_body_util::combinators::BoxBody;
+use hyper::{Request, Response, body::{Bytes, Incoming}};
+use json::{JsonValue, object};
+
+use crate::api::{control::{presence::{find_player, find_players, online_count}, storage::query::{get_user_friends, user_exists}}, typedef::{BackendError, routing::{Method, nodes::Node}}, utils::{get_body_url_args, response_json}};
+
+/**
+* How many players are online across the network.
+*/
+async fn online(_: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    Ok(response_json(object! { online: online_count().await }))
+}
+
+/**
+* Which server a player is on, 404 if they're offline.
+*/
+async fn find(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let uuid = args.get("uuid").ok_or(BackendError::new("uuid missing", 400))?;
+    let presence = find_player(uuid).await.ok_or(BackendError::new("Player is offline", 404))?;
+
+    Ok(response_json(presence.to_json()))
+}
+
+/**
+* The online friends of a player and the server each one is on.
+*/
+async fn friends(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let uuid = args.get("uuid").ok_or(BackendError::new("uuid missing", 400))?;
+
+    if !user_exists(uuid)? {
+        return Err(BackendError::new("User not found", 404));
+    }
+
+    let friends = get_user_friends(uuid)?;
+    let online = find_players(friends.iter().map(|f| f.uuid.as_ref())).await;
+
+    Ok(response_json(JsonValue::Array(online.iter().map(|p| p.to_json()).collect())))
+}
+
+pub async fn register(node: &mut Node) -> Result<(), Box<dyn Error + Send + Sync>> {
+    node.subnode("/presence")?
+        .endpoint("/online", Method::Get, online)?;
+
+    Ok(())
+}
+
+/**
+* Player lookups, registered under the privileged core node.
+*/
+pub fn register_privileged(core: &mut Node) -> Result<(), Box<dyn Error + Send + Sync>> {
+    core.subnode("/presence")?
+        .endpoint("/find", Method::Get, find)?
+        .endpoint("/friends", Method::Get, friends)?;
+
+    Ok(())
+}
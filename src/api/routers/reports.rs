@@ -0,0 +1,115 @@
+use std::{convert::Infallible, error::Error};
+
+use http_body_util::combinators::BoxBody;
+use hyper::{Request, Response, body::{Bytes, Incoming}};
+use json::{JsonValue, object};
+
+use crate::api::{control::storage::{query::user_exists, reports::{ReportFilter, claim_report, create_report, get_report, list_reports, resolve_report}}, routers::core::send_to_subscribers, typedef::{BackendError, jsonutils::SerializableJson, protocol::ServerPacket, report::ReportStatus, routing::{Method, nodes::Node}}, utils::{HttpTransaction, get_body_json, get_body_url_args, get_optional_url_args, response_json}};
+
+// Channel staff servers subscribe to for new reports, players' servers don't get them
+const REPORTS_CHANNEL: &str = "staff.reports";
+
+/**
+* Create a report, websocket clients subscribed to staff.reports are notified with a
+* REPORT_CREATED packet containing the report as json.
+*
+* Expects: body {
+*   reporter: string,
+*   target: string,
+*   reason: string,
+*   category: string (optional),
+*   server: string (optional),
+*   evidence: [string] (optional)
+* }
+*/
+async fn create(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+
+    let reporter = json["reporter"].as_str().ok_or(BackendError::new("reporter missing", 400))?;
+    let target = json["target"].as_str().ok_or(BackendError::new("target missing", 400))?;
+    let reason = json["reason"].as_str().ok_or(BackendError::new("reason missing", 400))?;
+    let category = json["category"].as_str().unwrap_or("other");
+    let server = json["server"].as_str().unwrap_or("unknown");
+    let evidence = json["evidence"].members().filter_map(|e| e.as_str().map(|s| s.into())).collect();
+
+    if reporter == target {
+        return Err(BackendError::new("Players can't report themselves", 400));
+    }
+    if !user_exists(target)? {
+        return Err(BackendError::new("User not found", 404));
+    }
+
+    let report = create_report(reporter, target, reason, category, server, evidence)?;
+    let json = report.to_json();
+
+    send_to_subscribers(REPORTS_CHANNEL, &ServerPacket::ReportCreated(json.clone())).await;
+
+    Ok(response_json(json))
+}
+
+/**
+* List reports newest first, filters: status (open, claimed, resolved), target, category, limit.
+*/
+async fn list(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_optional_url_args(&req)?;
+
+    let status = match args.get("status") {
+        Some(s) => Some(ReportStatus::try_from(s.as_ref())?),
+        None => None
+    };
+    let limit = args.get("limit").map(|l| l.parse::<usize>()).transpose().map_err(|_| BackendError::new("limit invalid", 400))?.unwrap_or(50).min(500);
+
+    let reports = list_reports(&ReportFilter {
+        status,
+        target: args.get("target").map(|t| t.as_ref()),
+        category: args.get("category").map(|c| c.as_ref()),
+        limit
+    })?;
+
+    Ok(response_json(object! {
+        reports: JsonValue::Array(reports.iter().map(|r| r.to_json()).collect())
+    }))
+}
+
+async fn get(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let id = args.get("id").ok_or(BackendError::new("Malformed url, id param is required", 400))?
+        .parse::<u64>().map_err(|_| BackendError::new("id invalid", 400))?;
+
+    Ok(response_json(get_report(id)?.ok_or(BackendError::new("Report not found", 404))?.to_json()))
+}
+
+async fn claim(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+    let id = json["id"].as_u64().ok_or(BackendError::new("id missing", 400))?;
+    let staff = json["staff"].as_str().ok_or(BackendError::new("staff missing", 400))?;
+
+    Ok(response_json(claim_report(id, staff)?.to_json()))
+}
+
+/**
+* Resolve a report, punishment_id can be set to link the punishment issued because of it.
+*/
+async fn resolve(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let json = get_body_json(HttpTransaction::Req(req)).await?;
+    let id = json["id"].as_u64().ok_or(BackendError::new("id missing", 400))?;
+    let staff = json["staff"].as_str().ok_or(BackendError::new("staff missing", 400))?;
+    let resolution = json["resolution"].as_str().unwrap_or("");
+    let punishment_id = json["punishment_id"].as_u64();
+
+    Ok(response_json(resolve_report(id, staff, resolution, punishment_id)?.to_json()))
+}
+
+/**
+* Reports are created by game servers, so every endpoint lives under the privileged core node.
+*/
+pub fn register_privileged(core: &mut Node) -> Result<(), Box<dyn Error + Send + Sync>> {
+    core.subnode("/reports")?
+        .endpoint("/create", Method::Post, create)?
+        .endpoint("/list", Method::Get, list)?
+        .endpoint("/get", Method::Get, get)?
+        .endpoint("/claim", Method::Put, claim)?
+        .endpoint("/resolve", Method::Put, resolve)?;
+
+    Ok(())
+}
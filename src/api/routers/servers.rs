@@ -0,0 +1,34 @@
+use std::{convert::Infallible, error::Error};
+
+use http_body_util::combinators::BoxBody;
+use hyper::{Request, Response, body::{Bytes, Incoming}};
+use json::JsonValue;
+
+use crate::api::{control::registry::{get_server, list_servers}, typedef::{BackendError, jsonutils::SerializableJson, routing::{Method, nodes::Node}}, utils::{get_body_url_args, get_optional_url_args, response_json}};
+
+/**
+* Servers connected to the network, optionally filtered by type and region. Used by the
+* launcher's server list.
+*/
+async fn list(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_optional_url_args(&req)?;
+    let servers = list_servers(args.get("type").map(|t| t.as_ref()), args.get("region").map(|r| r.as_ref())).await;
+
+    Ok(response_json(JsonValue::Array(servers.iter().map(|s| s.to_json()).collect())))
+}
+
+async fn get(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallible>>, BackendError> {
+    let args = get_body_url_args(&req)?;
+    let name = args.get("name").ok_or(BackendError::new("name missing", 400))?;
+    let server = get_server(name).await.ok_or(BackendError::new("Server not found", 404))?;
+
+    Ok(response_json(server.to_json()))
+}
+
+pub async fn register(node: &mut Node) -> Result<(), Box<dyn Error + Send + Sync>> {
+    node.subnode("/servers")?
+        .endpoint("/list", Method::Get, list)?
+        .endpoint("/get", Method::Get, get)?;
+
+    Ok(())
+}
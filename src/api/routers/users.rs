@@ -28,22 +28,41 @@ async fn get(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, Infallibl
         let token = token_header.unwrap();
         let token_str = token.to_str().map_err(|_| BackendError::new("Failed to parse header", 500))?;
 
-        let tokens = TOKENS.clone();
-        let mut tokens_map = tokens.lock().await;
-
-        if let Some(tupl) = tokens_map.get(token_str) {
-            let (saved_uuid, expires_at) = tupl;
-
-            if saved_uuid == uuid {
-                if *expires_at < Utc::now() {
-                    tokens_map.remove(token_str);
-                    return Err(BackendError::new("This token has expired or does not exist", 401));
-                }
-                return Ok(response_json(user.to_json()));
+        check_session_token(token_str, uuid).await?;
+        return Ok(response_json(user.to_json()));
+    }
+}
+
+/**
+* Checks that the token is a valid minecraft session of the given player, expired tokens are
+* removed.
+*/
+pub async fn check_session_token(token: &str, uuid: &str) -> Result<(), BackendError> {
+    let tokens = TOKENS.clone();
+    let mut tokens_map = tokens.lock().await;
+
+    if let Some(tupl) = tokens_map.get(token) {
+        let (saved_uuid, expires_at) = tupl;
+
+        if saved_uuid.as_ref() == uuid {
+            if *expires_at < Utc::now() {
+                tokens_map.remove(token);
+                return Err(BackendError::new("This token has expired or does not exist", 401));
             }
+            return Ok(());
         }
-        return Err(BackendError::new("This token has expired or does not exist", 401));
     }
+    Err(BackendError::new("This token has expired or does not exist", 401))
+}
+
+/**
+* Same as check_session_token, reading the token from the authorization header.
+*/
+pub async fn check_session(req: &Request<Incoming>, uuid: &str) -> Result<(), BackendError> {
+    let token = req.headers().get(AUTHORIZATION).ok_or(BackendError::new("Authorization header missing", 401))?;
+    let token_str = token.to_str().map_err(|_| BackendError::new("Failed to parse header", 500))?;
+
+    check_session_token(token_str, uuid).await
 }
 
 pub async fn register(node: &mut Node) -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -0,0 +1,106 @@
+use chrono::{DateTime, Utc};
+use json::{JsonValue, object};
+
+use crate::api::typedef::{BackendError, jsonutils::SerializableJson};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AppealStatus {
+    Pending,
+    Accepted,
+    Denied
+}
+
+pub struct AppealComment {
+    pub author: Box<str>,
+    pub text: Box<str>,
+    pub date: DateTime<Utc>
+}
+
+/**
+* A player's request to lift one of their punishments.
+*/
+pub struct Appeal {
+    pub id: u64,
+    pub uuid: Box<str>,
+    pub punishment_id: u64,
+    pub message: Box<str>,
+    pub status: AppealStatus,
+    pub created_at: DateTime<Utc>,
+    pub comments: Vec<AppealComment>,
+    pub handled_by: Option<Box<str>>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolution: Option<Box<str>>
+}
+
+impl AppealStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AppealStatus::Pending => "pending",
+            AppealStatus::Accepted => "accepted",
+            AppealStatus::Denied => "denied"
+        }
+    }
+}
+
+impl TryFrom<&str> for AppealStatus {
+    type Error = BackendError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "pending" => Ok(AppealStatus::Pending),
+            "accepted" => Ok(AppealStatus::Accepted),
+            "denied" => Ok(AppealStatus::Denied),
+            _ => Err(BackendError::new("Unknown appeal status", 400))
+        }
+    }
+}
+
+impl SerializableJson for AppealComment {
+    fn to_json(&self) -> JsonValue {
+        object! {
+            author: self.author.as_ref(),
+            text: self.text.as_ref(),
+            date: self.date.timestamp_millis()
+        }
+    }
+
+    fn from_json(json: &JsonValue) -> Result<Self, BackendError> where Self: Sized {
+        Ok(Self {
+            author: json["author"].as_str().ok_or(BackendError::new("comment.author missing", 400))?.into(),
+            text: json["text"].as_str().ok_or(BackendError::new("comment.text missing", 400))?.into(),
+            date: json["date"].as_i64().and_then(DateTime::from_timestamp_millis).unwrap_or(Utc::now())
+        })
+    }
+}
+
+impl SerializableJson for Appeal {
+    fn to_json(&self) -> JsonValue {
+        object! {
+            id: self.id,
+            uuid: self.uuid.as_ref(),
+            punishment_id: self.punishment_id,
+            message: self.message.as_ref(),
+            status: self.status.as_str(),
+            created_at: self.created_at.timestamp_millis(),
+            comments: JsonValue::Array(self.comments.iter().map(|c| c.to_json()).collect()),
+            handled_by: self.handled_by.as_deref(),
+            resolved_at: self.resolved_at.map(|d| d.timestamp_millis()),
+            resolution: self.resolution.as_deref()
+        }
+    }
+
+    fn from_json(json: &JsonValue) -> Result<Self, BackendError> where Self: Sized {
+        Ok(Self {
+            id: json["id"].as_u64().ok_or(BackendError::new("appeal.id missing", 400))?,
+            uuid: json["uuid"].as_str().ok_or(BackendError::new("appeal.uuid missing", 400))?.into(),
+            punishment_id: json["punishment_id"].as_u64().ok_or(BackendError::new("appeal.punishment_id missing", 400))?,
+            message: json["message"].as_str().ok_or(BackendError::new("appeal.message missing", 400))?.into(),
+            status: json["status"].as_str().ok_or(BackendError::new("appeal.status missing", 400))?.try_into()?,
+            created_at: json["created_at"].as_i64().and_then(DateTime::from_timestamp_millis).unwrap_or(Utc::now()),
+            comments: json["comments"].members().filter_map(|c| AppealComment::from_json(c).ok()).collect(),
+            handled_by: json["handled_by"].as_str().map(|s| s.into()),
+            resolved_at: json["resolved_at"].as_i64().and_then(DateTime::from_timestamp_millis),
+            resolution: json["resolution"].as_str().map(|s| s.into())
+        })
+    }
+}
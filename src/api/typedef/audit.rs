@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use json::{JsonValue, object};
+
+use crate::api::typedef::{BackendError, jsonutils::SerializableJson};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    Issue,
+    Revoke
+}
+
+/**
+* A moderation action, stored in the audit log.
+*/
+pub struct AuditEntry {
+    pub id: u64,
+    pub action: AuditAction,
+    pub punishment_id: u64,
+    pub target: Box<str>,
+    pub actor: Option<Box<str>>,
+    pub reason: Box<str>,
+    pub timestamp: DateTime<Utc>
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Issue => "issue",
+            AuditAction::Revoke => "revoke"
+        }
+    }
+}
+
+impl TryFrom<&str> for AuditAction {
+    type Error = BackendError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "issue" => Ok(AuditAction::Issue),
+            "revoke" => Ok(AuditAction::Revoke),
+            _ => Err(BackendError::new("Unknown audit action", 400))
+        }
+    }
+}
+
+impl SerializableJson for AuditEntry {
+    fn to_json(&self) -> JsonValue {
+        object! {
+            id: self.id,
+            action: self.action.as_str(),
+            punishment_id: self.punishment_id,
+            target: self.target.as_ref(),
+            actor: self.actor.as_deref(),
+            reason: self.reason.as_ref(),
+            timestamp: self.timestamp.timestamp_millis()
+        }
+    }
+
+    fn from_json(json: &JsonValue) -> Result<Self, BackendError> where Self: Sized {
+        Ok(Self {
+            id: json["id"].as_u64().ok_or(BackendError::new("audit.id missing", 400))?,
+            action: json["action"].as_str().ok_or(BackendError::new("audit.action missing", 400))?.try_into()?,
+            punishment_id: json["punishment_id"].as_u64().ok_or(BackendError::new("audit.punishment_id missing", 400))?,
+            target: json["target"].as_str().ok_or(BackendError::new("audit.target missing", 400))?.into(),
+            actor: json["actor"].as_str().map(|s| s.into()),
+            reason: json["reason"].as_str().unwrap_or("Unspecified").into(),
+            timestamp: json["timestamp"].as_i64().and_then(DateTime::from_timestamp_millis).unwrap_or(Utc::now())
+        })
+    }
+}
@@ -0,0 +1,207 @@
+use std::{error::Error, fs};
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use json::{JsonValue, object};
+
+use crate::api::{control::pubsub::channel_matches, typedef::protocol::{ClientPacket, ErrorCode, PacketError}};
+
+use super::Config;
+
+/**
+* A game server allowed to open the core websocket. secret_hash is the argon2 PHC string of
+* the secret it sends in X-Client-Secret. packets, targets and channels restrict what it can
+* do once connected, "*" allows everything.
+*
+* packets: propagate, target, request, cache, subscribe, publish, presence (responses and
+* registry packets are always allowed)
+* targets: names of the clients it can send TARGET and REQUEST packets to
+* channels: channel patterns it can subscribe and publish to, also the cache channels it can use
+*/
+pub struct WsIdentity {
+    pub name: Box<str>,
+    pub secret_hash: Box<str>,
+    pub packets: Vec<Box<str>>,
+    pub targets: Vec<Box<str>>,
+    pub channels: Vec<Box<str>>
+}
+
+pub struct WsIdentities {
+    pub identities: Vec<WsIdentity>
+}
+
+fn allows(list: &[Box<str>], value: &str) -> bool {
+    list.iter().any(|v| v.as_ref() == "*" || v.as_ref() == value)
+}
+
+fn read_list(json: &JsonValue) -> Vec<Box<str>> {
+    if json.is_null() {
+        return vec!["*".into()];
+    }
+    json.members().filter_map(|v| v.as_str().map(|v| v.into())).collect()
+}
+
+fn forbidden(msg: &str) -> PacketError {
+    PacketError::new(ErrorCode::Forbidden, msg)
+}
+
+impl WsIdentity {
+    pub fn verify(&self, secret: &str) -> bool {
+        PasswordHash::new(&self.secret_hash).is_ok_and(|hash| Argon2::default().verify_password(secret.as_bytes(), &hash).is_ok())
+    }
+
+    /**
+    * Checks a packet against the identity's restrictions. Subscription patterns must be
+    * covered by an allowed pattern, "games.*.start" is covered by "games.*".
+    */
+    pub fn check(&self, packet: &ClientPacket) -> Result<(), PacketError> {
+        let kind = match packet {
+            ClientPacket::Hello { .. } | ClientPacket::Response { .. } | ClientPacket::Unsubscribe { .. } => return Ok(()),
+            ClientPacket::RegisterServer(_) | ClientPacket::ServerStatus { .. } | ClientPacket::ListServers { .. } => return Ok(()),
+            ClientPacket::Propagate { .. } => "propagate",
+            ClientPacket::Target { .. } => "target",
+            ClientPacket::Request { .. } => "request",
+            ClientPacket::CacheRead { .. } | ClientPacket::CacheWrite { .. } | ClientPacket::CacheDelete { .. } => "cache",
+            ClientPacket::Subscribe { .. } => "subscribe",
+            ClientPacket::Publish { .. } => "publish",
+            ClientPacket::PlayerJoin { .. } | ClientPacket::PlayerQuit { .. } => "presence"
+        };
+        if !allows(&self.packets, kind) {
+            return Err(forbidden(&format!("{} can't send {kind} packets", self.name)));
+        }
+
+        match packet {
+            ClientPacket::Target { target, .. } | ClientPacket::Request { target, .. } if !allows(&self.targets, target) => {
+                Err(forbidden(&format!("{} can't send packets to {target}", self.name)))
+            },
+            ClientPacket::Subscribe { pattern: channel, .. } | ClientPacket::Publish { channel, .. }
+            | ClientPacket::CacheRead { channel, .. } | ClientPacket::CacheWrite { channel, .. } | ClientPacket::CacheDelete { channel, .. } if !self.channels.iter().any(|p| channel_matches(p, channel)) => {
+                Err(forbidden(&format!("{} can't use channel {channel}", self.name)))
+            },
+            _ => Ok(())
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        object! {
+            secret_hash: self.secret_hash.as_ref(),
+            packets: JsonValue::Array(self.packets.iter().map(|p| p.as_ref().into()).collect()),
+            targets: JsonValue::Array(self.targets.iter().map(|t| t.as_ref().into()).collect()),
+            channels: JsonValue::Array(self.channels.iter().map(|c| c.as_ref().into()).collect())
+        }
+    }
+}
+
+impl WsIdentities {
+    pub fn get(&self, name: &str) -> Option<&WsIdentity> {
+        self.identities.iter().find(|i| i.name.as_ref() == name)
+    }
+}
+
+impl Config for WsIdentities {
+    /**
+    * No identities, every server has to be added before it can connect.
+    */
+    fn default() -> Self {
+        Self { identities: vec![] }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut json = JsonValue::new_object();
+
+        for identity in &self.identities {
+            json[identity.name.as_ref()] = identity.to_json();
+        }
+        json
+    }
+
+    fn load(&mut self, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let str = fs::read_to_string(path)?;
+        let json = json::parse(&str)?;
+        let mut identities = vec![];
+
+        // An invalid entry only locks out that server
+        for (name, value) in json.entries() {
+            let Some(secret_hash) = value["secret_hash"].as_str() else {
+                eprintln!("[{path}] Skipping {name}: secret_hash missing");
+                continue;
+            };
+            if let Err(e) = PasswordHash::new(secret_hash) {
+                eprintln!("[{path}] Skipping {name}: invalid secret_hash, {e}");
+                continue;
+            }
+
+            identities.push(WsIdentity {
+                name: name.into(),
+                secret_hash: secret_hash.into(),
+                packets: read_list(&value["packets"]),
+                targets: read_list(&value["targets"]),
+                channels: read_list(&value["channels"])
+            });
+        }
+
+        self.identities = identities;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::{PasswordHasher, password_hash::SaltString};
+    use hyper::body::Bytes;
+
+    use super::*;
+
+    fn identity(packets: &[&str], targets: &[&str], channels: &[&str]) -> WsIdentity {
+        let list = |l: &[&str]| l.iter().map(|v| (*v).into()).collect();
+
+        WsIdentity { name: "lobby-1".into(), secret_hash: "".into(), packets: list(packets), targets: list(targets), channels: list(channels) }
+    }
+
+    #[test]
+    fn verifies_the_secret() {
+        let salt = SaltString::encode_b64(b"lobby-1 test salt").unwrap();
+        let mut identity = identity(&[], &[], &[]);
+        identity.secret_hash = Argon2::default().hash_password(b"secret", &salt).unwrap().to_string().into();
+
+        assert!(identity.verify("secret"));
+        assert!(!identity.verify("wrong"));
+        assert!(!WsIdentity { secret_hash: "not a hash".into(), ..identity }.verify("secret"));
+    }
+
+    #[test]
+    fn packet_kinds() {
+        let identity = identity(&["propagate"], &["*"], &["*"]);
+
+        assert!(identity.check(&ClientPacket::Propagate { payload: Bytes::new() }).is_ok());
+        assert!(identity.check(&ClientPacket::Hello { version: 1 }).is_ok());
+        assert!(identity.check(&ClientPacket::ServerStatus { players: 0, tps: 20.0 }).is_ok());
+        assert_eq!(identity.check(&ClientPacket::PlayerQuit { uuid: "a".into() }).unwrap_err().code, ErrorCode::Forbidden);
+        assert_eq!(identity.check(&ClientPacket::CacheDelete { channel: "a".into(), key: "b".into() }).unwrap_err().code, ErrorCode::Forbidden);
+    }
+
+    #[test]
+    fn targets() {
+        let identity = identity(&["*"], &["practice"], &["*"]);
+        let target = |target: &str| ClientPacket::Target { target: target.into(), payload: Bytes::new() };
+
+        assert!(identity.check(&target("practice")).is_ok());
+        assert!(identity.check(&target("survival")).is_err());
+        assert!(identity.check(&ClientPacket::Request { request_id: 1, target: "survival".into(), timeout_millis: 0, payload: Bytes::new() }).is_err());
+    }
+
+    #[test]
+    fn channel_coverage() {
+        let identity = identity(&["*"], &["*"], &["games.*", "parties.invites"]);
+        let subscribe = |pattern: &str| ClientPacket::Subscribe { pattern: pattern.into() };
+
+        assert!(identity.check(&subscribe("games.*.start")).is_ok());
+        assert!(identity.check(&subscribe("games.*")).is_ok());
+        assert!(identity.check(&subscribe("parties.invites")).is_ok());
+        assert!(identity.check(&subscribe("parties.*")).is_err());
+        assert!(identity.check(&subscribe("*")).is_err());
+        assert!(identity.check(&ClientPacket::Publish { channel: "games.bedwars.start".into(), payload: Bytes::new() }).is_ok());
+        assert!(identity.check(&ClientPacket::CacheRead { channel: "queues.ranked".into(), key: "a".into() }).is_err());
+        // Unsubscribing is always allowed
+        assert!(identity.check(&ClientPacket::Unsubscribe { pattern: "*".into() }).is_ok());
+    }
+}
@@ -1,5 +1,8 @@
 pub mod state;
 pub mod redirects;
+pub mod templates;
+pub mod identities;
+pub mod websocket;
 
 use std::{error::Error, fs, sync::{Arc, Mutex}};
 
@@ -11,10 +14,16 @@ pub trait Config: Sized + Send + Sync + 'static {
     fn open(path: &str, watcher: &mut DirWatcher) -> Result<Arc<Mutex<Self>>, Box<dyn Error + Send + Sync>> {
         let mut conf = Self::default();
 
-        if conf.load_async(path).is_err() {
-            println!("{path} doesn't seem to exist, creating default config...");
-            if let Err(err) = conf.save(path) {
-                eprintln!("Failed to save file: {}", err.to_string());
+        // A file that fails to parse is kept so a typo doesn't wipe it, the defaults are used
+        // until it's fixed
+        if let Err(err) = conf.load_async(path) {
+            if fs::exists(path).unwrap_or(true) {
+                eprintln!("Failed to load {path}, using the default config until it's fixed: {err}");
+            } else {
+                println!("{path} doesn't seem to exist, creating default config...");
+                if let Err(err) = conf.save(path) {
+                    eprintln!("Failed to save file: {}", err.to_string());
+                }
             }
         }
 
@@ -0,0 +1,284 @@
+use std::{error::Error, fs};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use json::{JsonValue, object};
+
+use crate::api::typedef::{network::{DEFAULT_IPV4_PREFIX, DEFAULT_IPV6_PREFIX, IPV4_PREFIXES, IPV6_PREFIXES, read_prefix}, punishment::{BAN_TYPE, MUTE_TYPE, Punishment}};
+
+use super::Config;
+
+/**
+* One rung of an escalation ladder. A missing duration means the punishment is permanent.
+*/
+pub struct TemplateStep {
+    pub r#type: Box<str>,
+    pub duration: Option<TimeDelta>,
+    pub alsoip: bool,
+    pub ipv4_prefix: u8,
+    pub ipv6_prefix: u8,
+    pub allow_chat: bool,
+    pub allow_ranked: bool,
+    pub allow_unranked: bool,
+    pub allow_join_minigames: bool
+}
+
+pub struct PunishmentTemplate {
+    pub name: Box<str>,
+    pub title: Box<str>,
+    pub reason: Box<str>,
+    pub ladder: Vec<TemplateStep>
+}
+
+pub struct PunishmentTemplates {
+    pub templates: Vec<PunishmentTemplate>
+}
+
+/**
+* Parses durations such as "30m", "1h", "7d" or "2w", "permanent" means no duration.
+*/
+fn parse_duration(s: &str) -> Result<Option<TimeDelta>, Box<dyn Error + Send + Sync>> {
+    if s == "permanent" {
+        return Ok(None);
+    }
+    if s.len() < 2 {
+        return Err(format!("Invalid duration {s}").into());
+    }
+
+    let (amount, unit) = s.split_at(s.len() - 1);
+    let amount: i64 = amount.parse()?;
+
+    Ok(Some(match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => None
+    }.ok_or(format!("Invalid duration {s}"))?))
+}
+
+fn format_duration(duration: &Option<TimeDelta>) -> String {
+    match duration {
+        Some(d) if d.num_seconds() % 604800 == 0 => format!("{}w", d.num_weeks()),
+        Some(d) if d.num_seconds() % 86400 == 0 => format!("{}d", d.num_days()),
+        Some(d) if d.num_seconds() % 3600 == 0 => format!("{}h", d.num_hours()),
+        Some(d) if d.num_seconds() % 60 == 0 => format!("{}m", d.num_minutes()),
+        Some(d) => format!("{}s", d.num_seconds()),
+        None => "permanent".into()
+    }
+}
+
+impl TemplateStep {
+    /**
+    * A step whose restrictions follow its type: bans deny everything, mutes deny chat and other
+    * types deny nothing.
+    */
+    fn new(r#type: &str, duration: Option<TimeDelta>) -> Self {
+        let ban = r#type.eq_ignore_ascii_case(BAN_TYPE);
+        let mute = r#type.eq_ignore_ascii_case(MUTE_TYPE);
+
+        Self {
+            r#type: r#type.into(), duration, alsoip: false,
+            ipv4_prefix: DEFAULT_IPV4_PREFIX, ipv6_prefix: DEFAULT_IPV6_PREFIX,
+            allow_chat: !ban && !mute, allow_ranked: !ban, allow_unranked: !ban, allow_join_minigames: !ban
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        object! {
+            type: self.r#type.as_ref(),
+            duration: format_duration(&self.duration),
+            alsoip: self.alsoip,
+            ipv4_prefix: self.ipv4_prefix,
+            ipv6_prefix: self.ipv6_prefix,
+            allow_chat: self.allow_chat,
+            allow_ranked: self.allow_ranked,
+            allow_unranked: self.allow_unranked,
+            allow_join_minigames: self.allow_join_minigames
+        }
+    }
+
+    /**
+    * Missing restrictions default to the ones of the step's type, see new.
+    */
+    fn from_json(json: &JsonValue) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let r#type = json["type"].as_str().ok_or("step.type missing")?;
+        let duration = match json["duration"].as_str() {
+            Some(d) => parse_duration(d)?,
+            None => None
+        };
+        let defaults = Self::new(r#type, duration);
+
+        Ok(Self {
+            alsoip: json["alsoip"].as_bool().unwrap_or(defaults.alsoip),
+            ipv4_prefix: read_prefix(json, "ipv4_prefix", defaults.ipv4_prefix, IPV4_PREFIXES)?,
+            ipv6_prefix: read_prefix(json, "ipv6_prefix", defaults.ipv6_prefix, IPV6_PREFIXES)?,
+            allow_chat: json["allow_chat"].as_bool().unwrap_or(defaults.allow_chat),
+            allow_ranked: json["allow_ranked"].as_bool().unwrap_or(defaults.allow_ranked),
+            allow_unranked: json["allow_unranked"].as_bool().unwrap_or(defaults.allow_unranked),
+            allow_join_minigames: json["allow_join_minigames"].as_bool().unwrap_or(defaults.allow_join_minigames),
+            ..defaults
+        })
+    }
+}
+
+impl PunishmentTemplate {
+    /**
+    * The step for a player that already has `offences` punishments from this template, the last
+    * step is repeated once the ladder is exhausted.
+    */
+    pub fn get_step(&self, offences: usize) -> Option<&TemplateStep> {
+        self.ladder.get(offences.min(self.ladder.len().saturating_sub(1)))
+    }
+
+    /**
+    * Builds the punishment for the given step, the id is assigned when it's stored.
+    */
+    pub fn build(&self, step: &TemplateStep, issuer: Option<&str>, reason: Option<&str>, now: DateTime<Utc>) -> Punishment {
+        Punishment {
+            id: 0, title: self.title.clone(),
+            r#type: step.r#type.clone(),
+            creation_date: now,
+            expiration_date: step.duration.map(|d| now + d),
+            reason: reason.unwrap_or(&self.reason).into(),
+            alsoip: step.alsoip, subnet: None,
+            allow_chat: step.allow_chat,
+            allow_ranked: step.allow_ranked,
+            allow_unranked: step.allow_unranked,
+            allow_join_minigames: step.allow_join_minigames,
+            issuer: issuer.map(|i| i.into()),
+            revoked_by: None, revoked_at: None, revoke_reason: None,
+            template: Some(self.name.clone())
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        object! {
+            title: self.title.as_ref(),
+            reason: self.reason.as_ref(),
+            ladder: JsonValue::Array(self.ladder.iter().map(|s| s.to_json()).collect())
+        }
+    }
+}
+
+impl PunishmentTemplates {
+    pub fn get(&self, name: &str) -> Option<&PunishmentTemplate> {
+        self.templates.iter().find(|t| t.name.as_ref() == name)
+    }
+}
+
+impl Config for PunishmentTemplates {
+    fn default() -> Self {
+        Self { templates: vec![PunishmentTemplate {
+            name: "chat_abuse".into(),
+            title: "Chat abuse".into(),
+            reason: "Abusive behaviour in chat".into(),
+            ladder: vec![
+                TemplateStep::new(MUTE_TYPE, TimeDelta::try_hours(1)),
+                TemplateStep::new(MUTE_TYPE, TimeDelta::try_days(1)),
+                TemplateStep::new(MUTE_TYPE, TimeDelta::try_days(7)),
+                TemplateStep::new(BAN_TYPE, None)
+            ]
+        }] }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut json = JsonValue::new_object();
+
+        for template in &self.templates {
+            json[template.name.as_ref()] = template.to_json();
+        }
+        json
+    }
+
+    fn load(&mut self, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let str = fs::read_to_string(path)?;
+        let json = json::parse(&str)?;
+        let mut templates = vec![];
+
+        for (name, value) in json.entries() {
+            let mut ladder = vec![];
+            for step in value["ladder"].members() {
+                ladder.push(TemplateStep::from_json(step).map_err(|e| format!("{name}: {e}"))?);
+            }
+            if ladder.is_empty() {
+                return Err(format!("{name}: the ladder needs at least one step").into());
+            }
+
+            templates.push(PunishmentTemplate {
+                name: name.into(),
+                title: value["title"].as_str().unwrap_or(name).into(),
+                reason: value["reason"].as_str().unwrap_or("Unspecified").into(),
+                ladder
+            });
+        }
+
+        self.templates = templates;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_ladder(types: &[&str]) -> PunishmentTemplate {
+        PunishmentTemplate {
+            name: "test".into(), title: "Test".into(), reason: "Testing".into(),
+            ladder: types.iter().map(|t| TemplateStep::new(t, None)).collect()
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("30s").unwrap(), TimeDelta::try_seconds(30));
+        assert_eq!(parse_duration("30m").unwrap(), TimeDelta::try_minutes(30));
+        assert_eq!(parse_duration("1h").unwrap(), TimeDelta::try_hours(1));
+        assert_eq!(parse_duration("7d").unwrap(), TimeDelta::try_days(7));
+        assert_eq!(parse_duration("2w").unwrap(), TimeDelta::try_weeks(2));
+        assert_eq!(parse_duration("permanent").unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_durations() {
+        for invalid in ["", "h", "1", "1y", "xh", "1.5h", "Permanent"] {
+            assert!(parse_duration(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn durations_are_written_in_the_largest_unit() {
+        for duration in ["45s", "90m", "36h", "6d", "2w", "permanent"] {
+            assert_eq!(format_duration(&parse_duration(duration).unwrap()), duration);
+        }
+        assert_eq!(format_duration(&TimeDelta::try_minutes(120)), "2h");
+        assert_eq!(format_duration(&TimeDelta::try_days(14)), "2w");
+    }
+
+    #[test]
+    fn ladder_steps_follow_offences() {
+        let template = with_ladder(&["warn", "mute", "ban"]);
+        let step = |offences| template.get_step(offences).map(|s| s.r#type.as_ref());
+
+        assert_eq!(step(0), Some("warn"));
+        assert_eq!(step(1), Some("mute"));
+        assert_eq!(step(2), Some("ban"));
+        assert_eq!(step(9), Some("ban"));
+        assert!(with_ladder(&[]).get_step(0).is_none());
+    }
+
+    #[test]
+    fn step_restrictions_default_to_the_type() {
+        let ban = TemplateStep::from_json(&object! { type: "ban" }).unwrap();
+        let mute = TemplateStep::from_json(&object! { type: "Mute", duration: "1d", allow_ranked: false }).unwrap();
+        let warn = TemplateStep::from_json(&object! { type: "warn" }).unwrap();
+
+        assert!(!ban.allow_chat && !ban.allow_ranked && !ban.allow_unranked && !ban.allow_join_minigames);
+        assert!(ban.duration.is_none());
+        assert!(!mute.allow_chat && !mute.allow_ranked && mute.allow_unranked && mute.allow_join_minigames);
+        assert_eq!(mute.duration, TimeDelta::try_days(1));
+        assert!(warn.allow_chat && warn.allow_ranked && warn.allow_unranked && warn.allow_join_minigames);
+        assert!(TemplateStep::from_json(&object! { duration: "1d" }).is_err());
+        assert!(TemplateStep::from_json(&object! { type: "ban", ipv4_prefix: 0 }).is_err());
+    }
+}
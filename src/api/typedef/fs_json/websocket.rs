@@ -0,0 +1,105 @@
+use std::{error::Error, fs, time::Duration};
+
+use json::{JsonValue, object};
+
+use super::Config;
+
+/**
+* What happens when a client's outbound queue is full, the message is dropped either way.
+*/
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    Drop,
+    Disconnect
+}
+
+/**
+* Core websocket settings, read when a client connects so changes apply to new sessions.
+*
+* The backend pings every ping_interval and evicts a client after max_missed_pongs pings
+* without hearing from it, any frame counts as a pong. A client must send its HELLO within
+* handshake_timeout. With takeover, a client connecting with valid credentials under a name
+* whose session missed max_missed_pongs pings (at least two) replaces that session instead of
+* being refused, a responsive session is never replaced.
+* Each client has an outbound queue of queue_capacity messages, overflow_policy decides what
+* happens to slow clients that fill it.
+*/
+#[derive(Clone)]
+pub struct WsSettings {
+    pub ping_interval: Duration,
+    pub max_missed_pongs: u32,
+    pub handshake_timeout: Duration,
+    pub takeover: bool,
+    pub queue_capacity: usize,
+    pub overflow_policy: OverflowPolicy
+}
+
+impl OverflowPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Drop => "drop",
+            Self::Disconnect => "disconnect"
+        }
+    }
+}
+
+impl TryFrom<&str> for OverflowPolicy {
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "drop" => Ok(Self::Drop),
+            "disconnect" => Ok(Self::Disconnect),
+            _ => Err(format!("Invalid overflow_policy {value}, expected drop or disconnect").into())
+        }
+    }
+}
+
+impl Config for WsSettings {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(15),
+            max_missed_pongs: 2,
+            handshake_timeout: Duration::from_secs(10),
+            takeover: true,
+            queue_capacity: 1024,
+            overflow_policy: OverflowPolicy::Drop
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        object! {
+            ping_interval_secs: self.ping_interval.as_secs(),
+            max_missed_pongs: self.max_missed_pongs,
+            handshake_timeout_secs: self.handshake_timeout.as_secs(),
+            takeover: self.takeover,
+            queue_capacity: self.queue_capacity,
+            overflow_policy: self.overflow_policy.as_str()
+        }
+    }
+
+    fn load(&mut self, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let str = fs::read_to_string(path)?;
+        let json = json::parse(&str)?;
+        let default = <Self as Config>::default();
+
+        let ping_interval = json["ping_interval_secs"].as_u64().map(Duration::from_secs).unwrap_or(default.ping_interval);
+        let handshake_timeout = json["handshake_timeout_secs"].as_u64().map(Duration::from_secs).unwrap_or(default.handshake_timeout);
+        if ping_interval.is_zero() || handshake_timeout.is_zero() {
+            return Err("ping_interval_secs and handshake_timeout_secs must be positive".into());
+        }
+
+        *self = Self {
+            ping_interval,
+            max_missed_pongs: json["max_missed_pongs"].as_u32().unwrap_or(default.max_missed_pongs).max(1),
+            handshake_timeout,
+            takeover: json["takeover"].as_bool().unwrap_or(default.takeover),
+            queue_capacity: json["queue_capacity"].as_usize().unwrap_or(default.queue_capacity).max(1),
+            overflow_policy: match json["overflow_policy"].as_str() {
+                Some(policy) => policy.try_into()?,
+                None => default.overflow_policy
+            }
+        };
+        Ok(())
+    }
+}
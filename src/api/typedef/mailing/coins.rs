@@ -8,6 +8,7 @@ use super::Mail;
 pub const COINS_SERIAL_ID: u8 = 1;
 
 pub struct Coins {
+    id: u64,
     message: Box<str>,
     submission_date: DateTime<Utc>,
     sender: Box<str>,
@@ -22,6 +23,7 @@ impl Mail for Coins {
         let submission_date = if let Some(str) = submission_date_opt {DateTime::from_str(str).unwrap_or(Utc::now())} else {Utc::now()};
 
         Self {
+            id: value["id"].as_u64().unwrap_or(0),
             message: value["msg"].as_str().unwrap_or("Message not provided.").into(),
             submission_date,
             sender: value["sender"].as_str().unwrap_or("Unknown sender").into(),
@@ -31,6 +33,10 @@ impl Mail for Coins {
         }
     }
 
+    fn get_id(&self) -> u64 {
+        self.id
+    }
+
     fn get_serial_id(&self) -> u8 {
         COINS_SERIAL_ID
     }
@@ -49,6 +55,7 @@ impl Mail for Coins {
 
     fn to_json(&self) -> JsonValue {
         object! {
+            "id": self.id,
             "type": COINS_SERIAL_ID,
             "msg": self.message.as_ref(),
             "submission_date": self.submission_date.to_string(),
@@ -10,18 +10,26 @@ use super::Mail;
 pub const MESSAGE_SERIAL_ID: u8 = 0;
 
 pub struct Message {
+    id: u64,
     message: Box<str>,
     submission_date: DateTime<Utc>,
     sender: Box<str>,
     is_deleted: bool
 }
 
+impl Message {
+    pub fn new(sender: &str, message: &str) -> Self {
+        Self { id: 0, message: message.into(), submission_date: Utc::now(), sender: sender.into(), is_deleted: false }
+    }
+}
+
 impl Mail for Message {
     fn from_json(value: &JsonValue) -> Self {
         let submission_date_opt = value["submission_date"].as_str();
         let submission_date = if let Some(str) = submission_date_opt {DateTime::from_str(str).unwrap_or(Utc::now())} else {Utc::now()};
 
         Self {
+            id: value["id"].as_u64().unwrap_or(0),
             message: value["msg"].as_str().unwrap_or("Message not provided.").into(),
             submission_date,
             sender: value["sender"].as_str().unwrap_or("Unknown sender").into(),
@@ -29,6 +37,10 @@ impl Mail for Message {
         }
     }
 
+    fn get_id(&self) -> u64 {
+        self.id
+    }
+
     fn get_serial_id(&self) -> u8 {
         MESSAGE_SERIAL_ID
     }
@@ -47,6 +59,7 @@ impl Mail for Message {
 
     fn to_json(&self) -> JsonValue {
         object! {
+            "id": self.id,
             "type": MESSAGE_SERIAL_ID,
             "msg": self.message.as_ref(),
             "submission_date": self.submission_date.to_string(),
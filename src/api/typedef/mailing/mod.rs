@@ -9,6 +9,10 @@ use json::JsonValue;
 
 pub trait Mail: Send + Sync {
     fn from_json(json: &JsonValue) -> Self where Self: Sized;
+    /**
+    * Assigned by push_mail, 0 until the mail is in an inbox.
+    */
+    fn get_id(&self) -> u64;
     fn get_serial_id(&self) -> u8;
     fn get_sender(&self) -> &str;
     fn get_submission_date(&self) -> &DateTime<Utc>;
@@ -44,7 +48,3 @@ pub fn get_mails_from_json(json: &JsonValue) -> Vec<Box<dyn Mail>> {
 
     res
 }
-
-pub fn get_json_from_mails(mails: &Vec<Box<dyn Mail>>) -> JsonValue {
-    JsonValue::Array(mails.iter().map(|m| m.to_json()).collect())
-}
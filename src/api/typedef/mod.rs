@@ -7,9 +7,18 @@ pub mod fs_json;
 pub mod routing;
 pub mod jsonutils;
 pub mod punishment;
+pub mod network;
+pub mod audit;
+pub mod appeal;
+pub mod report;
+pub mod note;
+pub mod protocol;
+pub mod server;
 
 pub use user::User;
 pub use user::UserMapping;
+pub use user::AltAccount;
+pub use user::PmsMode;
 pub use microsoft::SigninState;
 pub use microsoft::UserCredentials;
 pub use microsoft::MinecraftData;
@@ -17,7 +26,6 @@ pub use microsoft::XboxLiveTokensData;
 pub use microsoft::MicrosoftTokens;
 pub use microsoft::XstsData;
 pub use http::BackendError;
-pub use http::CacheData;
 
 use json::JsonValue;
 use std::path::Path;
@@ -0,0 +1,229 @@
+use std::{fmt::Display, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr}, ops::RangeInclusive, str::FromStr};
+
+use json::JsonValue;
+
+use crate::api::typedef::BackendError;
+
+pub const DEFAULT_IPV4_PREFIX: u8 = 24;
+pub const DEFAULT_IPV6_PREFIX: u8 = 64;
+// Allowed prefix lengths, wider subnets would punish whole providers (a /0 matches everybody)
+pub const IPV4_PREFIXES: RangeInclusive<u8> = 8..=32;
+pub const IPV6_PREFIXES: RangeInclusive<u8> = 32..=128;
+
+/**
+* A CIDR block, the address is always stored already masked to its network part.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Subnet {
+    network: IpAddr,
+    prefix: u8
+}
+
+/**
+* Parses an address as sent by game servers, accepts plain addresses, socket addresses
+* ("1.2.3.4:25565", "[::1]:25565") and scoped IPv6 addresses ("fe80::1%eth0").
+* IPv4-mapped IPv6 addresses are converted to plain IPv4.
+*/
+pub fn parse_address(address: &str) -> Result<IpAddr, BackendError> {
+    let address = address.trim();
+    let unscoped = address.split('%').next().unwrap_or(address);
+
+    if let Ok(ip) = IpAddr::from_str(unscoped) {
+        return Ok(ip.to_canonical());
+    }
+    if let Ok(sock) = SocketAddr::from_str(address) {
+        return Ok(sock.ip().to_canonical());
+    }
+
+    Err(BackendError::new("Bad ip address", 400))
+}
+
+pub fn max_prefix(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128
+    }
+}
+
+pub fn family(addr: &IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 4,
+        IpAddr::V6(_) => 6
+    }
+}
+
+fn allowed_prefixes(addr: &IpAddr) -> RangeInclusive<u8> {
+    match addr {
+        IpAddr::V4(_) => IPV4_PREFIXES,
+        IpAddr::V6(_) => IPV6_PREFIXES
+    }
+}
+
+/**
+* Reads an optional prefix length from json, default if it's missing. A value that is present
+* must be a number in allowed.
+*/
+pub fn read_prefix(json: &JsonValue, key: &str, default: u8, allowed: RangeInclusive<u8>) -> Result<u8, BackendError> {
+    if json[key].is_null() {
+        return Ok(default);
+    }
+
+    json[key].as_u8().filter(|p| allowed.contains(p))
+        .ok_or(BackendError::new(&format!("{key} must be a number from {} to {}", allowed.start(), allowed.end()), 400))
+}
+
+fn mask(addr: &IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let bits = u32::from(*v4);
+            let mask = if prefix == 0 { 0 } else { u32::MAX << (32 - prefix as u32) };
+            IpAddr::V4(Ipv4Addr::from(bits & mask))
+        },
+        IpAddr::V6(v6) => {
+            let bits = u128::from(*v6);
+            let mask = if prefix == 0 { 0 } else { u128::MAX << (128 - prefix as u32) };
+            IpAddr::V6(Ipv6Addr::from(bits & mask))
+        }
+    }
+}
+
+impl Subnet {
+    /**
+    * The subnet of addr, prefix must be in IPV4_PREFIXES or IPV6_PREFIXES depending on the family.
+    */
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, BackendError> {
+        let addr = addr.to_canonical();
+        let allowed = allowed_prefixes(&addr);
+        if !allowed.contains(&prefix) {
+            return Err(BackendError::new(&format!("Subnet prefix length must be {} to {}", allowed.start(), allowed.end()), 400));
+        }
+
+        Ok(Self { network: mask(&addr, prefix), prefix })
+    }
+
+    /**
+    * Builds the subnet an address belongs to, picking the prefix length that matches its family.
+    */
+    pub fn for_address(addr: IpAddr, ipv4_prefix: u8, ipv6_prefix: u8) -> Result<Self, BackendError> {
+        let addr = addr.to_canonical();
+        let prefix = if addr.is_ipv4() { ipv4_prefix } else { ipv6_prefix };
+
+        Self::new(addr, prefix)
+    }
+
+    /**
+    * The subnets of the given prefix lengths that contain the address, used to look up subnet
+    * keyed entries. Prefixes that aren't allowed for the address family are skipped.
+    */
+    pub fn enclosing(addr: &IpAddr, prefixes: impl IntoIterator<Item = u8>) -> impl Iterator<Item = Subnet> {
+        let addr = addr.to_canonical();
+
+        prefixes.into_iter().filter_map(move |prefix| Self::new(addr, prefix).ok())
+    }
+
+    /**
+    * The address family byte and prefix length, keys of the index of prefixes in use.
+    */
+    pub fn prefix_key(&self) -> [u8; 2] {
+        [family(&self.network), self.prefix]
+    }
+}
+
+impl Display for Subnet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = BackendError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (parse_address(addr)?, Some(prefix.parse::<u8>().map_err(|_| BackendError::new("Bad subnet prefix", 400))?)),
+            None => (parse_address(s)?, None)
+        };
+
+        Self::new(addr, prefix.unwrap_or(max_prefix(&addr)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_address_forms() {
+        let v4: IpAddr = "1.2.3.4".parse().unwrap();
+
+        assert_eq!(parse_address(" 1.2.3.4 ").unwrap(), v4);
+        assert_eq!(parse_address("1.2.3.4:25565").unwrap(), v4);
+        assert_eq!(parse_address("::ffff:1.2.3.4").unwrap(), v4);
+        assert_eq!(parse_address("[::1]:25565").unwrap(), "::1".parse::<IpAddr>().unwrap());
+        assert_eq!(parse_address("fe80::1%eth0").unwrap(), "fe80::1".parse::<IpAddr>().unwrap());
+        assert!(parse_address("not an address").is_err());
+    }
+
+    #[test]
+    fn subnets_are_masked() {
+        assert_eq!(Subnet::from_str("1.2.3.4/24").unwrap().to_string(), "1.2.3.0/24");
+        assert_eq!(Subnet::from_str("1.2.3.4").unwrap().to_string(), "1.2.3.4/32");
+        assert_eq!(Subnet::from_str("2001:db8:aaaa:bbbb::1/64").unwrap().to_string(), "2001:db8:aaaa:bbbb::/64");
+        assert_eq!(Subnet::from_str("::ffff:10.1.2.3/8").unwrap().to_string(), "10.0.0.0/8");
+    }
+
+    #[test]
+    fn invalid_subnets() {
+        assert!(Subnet::from_str("1.2.3.4/33").is_err());
+        assert!(Subnet::from_str("::1/129").is_err());
+        assert!(Subnet::from_str("1.2.3.4/abc").is_err());
+    }
+
+    #[test]
+    fn subnets_have_a_minimum_prefix() {
+        assert!(Subnet::from_str("0.0.0.0/0").is_err());
+        assert!(Subnet::from_str("10.0.0.0/7").is_err());
+        assert!(Subnet::from_str("::/0").is_err());
+        assert!(Subnet::from_str("2001:db8::/31").is_err());
+        assert!(Subnet::for_address("1.2.3.4".parse().unwrap(), 0, DEFAULT_IPV6_PREFIX).is_err());
+        assert_eq!(Subnet::from_str("10.1.2.3/8").unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!(Subnet::from_str("2001:db8::1/32").unwrap().to_string(), "2001:db8::/32");
+    }
+
+    #[test]
+    fn prefixes_read_from_json() {
+        let json = json::object! { v4: 16, zero: 0, big: 300, text: "24", fraction: 24.5 };
+
+        assert_eq!(read_prefix(&json, "v4", DEFAULT_IPV4_PREFIX, IPV4_PREFIXES).unwrap(), 16);
+        assert_eq!(read_prefix(&json, "missing", DEFAULT_IPV4_PREFIX, IPV4_PREFIXES).unwrap(), DEFAULT_IPV4_PREFIX);
+        for invalid in ["zero", "big", "text", "fraction"] {
+            assert_eq!(*read_prefix(&json, invalid, DEFAULT_IPV4_PREFIX, IPV4_PREFIXES).unwrap_err().get_status(), 400, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn for_address_picks_the_family_prefix() {
+        let v4 = Subnet::for_address("1.2.3.4".parse().unwrap(), DEFAULT_IPV4_PREFIX, DEFAULT_IPV6_PREFIX).unwrap();
+        let v6 = Subnet::for_address("2001:db8::1".parse().unwrap(), DEFAULT_IPV4_PREFIX, DEFAULT_IPV6_PREFIX).unwrap();
+
+        assert_eq!(v4.to_string(), "1.2.3.0/24");
+        assert_eq!(v6.to_string(), "2001:db8::/64");
+    }
+
+    #[test]
+    fn enclosing_only_uses_the_given_prefixes() {
+        let addr: IpAddr = "1.2.3.4".parse().unwrap();
+        let subnets: Vec<String> = Subnet::enclosing(&addr, [8, 24, 32, 64]).map(|s| s.to_string()).collect();
+
+        assert_eq!(subnets, ["1.0.0.0/8", "1.2.3.0/24", "1.2.3.4/32"]);
+        assert_eq!(Subnet::enclosing(&"::ffff:1.2.3.4".parse().unwrap(), [24]).count(), 1);
+        assert_eq!(Subnet::enclosing(&"2001:db8::1".parse().unwrap(), [24, 48, 64]).count(), 2);
+    }
+
+    #[test]
+    fn prefix_keys() {
+        assert_eq!(Subnet::from_str("1.2.3.0/24").unwrap().prefix_key(), [4, 24]);
+        assert_eq!(Subnet::from_str("::ffff:1.2.3.0/24").unwrap().prefix_key(), [4, 24]);
+        assert_eq!(Subnet::from_str("2001:db8::/64").unwrap().prefix_key(), [6, 64]);
+    }
+}
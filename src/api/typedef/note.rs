@@ -0,0 +1,37 @@
+use chrono::{DateTime, Utc};
+use json::{JsonValue, object};
+
+use crate::api::typedef::{BackendError, jsonutils::SerializableJson};
+
+/**
+* A private staff note about a player, it has no effect on the player.
+*/
+pub struct Note {
+    pub id: u64,
+    pub uuid: Box<str>,
+    pub author: Box<str>,
+    pub text: Box<str>,
+    pub created_at: DateTime<Utc>
+}
+
+impl SerializableJson for Note {
+    fn to_json(&self) -> JsonValue {
+        object! {
+            id: self.id,
+            uuid: self.uuid.as_ref(),
+            author: self.author.as_ref(),
+            text: self.text.as_ref(),
+            created_at: self.created_at.timestamp_millis()
+        }
+    }
+
+    fn from_json(json: &JsonValue) -> Result<Self, BackendError> where Self: Sized {
+        Ok(Self {
+            id: json["id"].as_u64().ok_or(BackendError::new("note.id missing", 400))?,
+            uuid: json["uuid"].as_str().ok_or(BackendError::new("note.uuid missing", 400))?.into(),
+            author: json["author"].as_str().ok_or(BackendError::new("note.author missing", 400))?.into(),
+            text: json["text"].as_str().ok_or(BackendError::new("note.text missing", 400))?.into(),
+            created_at: json["created_at"].as_i64().and_then(DateTime::from_timestamp_millis).unwrap_or(Utc::now())
+        })
+    }
+}
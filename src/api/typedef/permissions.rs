@@ -1,22 +1,239 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{DateTime, Utc};
 use json::{JsonValue, object};
 
 use crate::api::typedef::{BackendError, jsonutils::SerializableJson};
 
+/**
+* Where a permission or group membership applies, key=value pairs such as server=survival or
+* world=nether. Empty contexts apply everywhere. Written as "server=survival,world=nether".
+*/
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Contexts(Vec<(Box<str>, Box<str>)>);
+
+/**
+* A permission node, expires_at is only used for timed user permissions.
+*/
+#[derive(Clone)]
 pub struct Permission {
     pub perm: Box<str>,
-    pub value: bool
+    pub value: bool,
+    pub contexts: Contexts,
+    pub expires_at: Option<DateTime<Utc>>
 }
 
+/**
+* A group can inherit from several parents, weight decides which group wins when a user holds
+* more than one (prefix, suffix and permissions) and the order groups are displayed in.
+* effective_perms is computed when the group is loaded: the declared perms followed by the
+* inherited ones that aren't overridden.
+*/
 pub struct Group {
     pub name: Box<str>,
     pub prefix: Box<str>,
     pub suffix: Box<str>,
     pub perms: Vec<Permission>,
+    pub parents: Vec<Box<str>>,
+    pub weight: i32,
+    pub effective_perms: Vec<Permission>
+}
+
+/**
+* A group held by a user, only in the given contexts and until expires_at if set.
+*/
+pub struct Membership {
+    pub group: Group,
+    pub contexts: Contexts,
+    pub expires_at: Option<DateTime<Utc>>
+}
+
+/**
+* The permissions that apply to a user: their own nodes and their groups', highest weight first.
+*/
+pub struct PermissionHolder {
+    pub perms: Vec<Permission>,
+    pub groups: Vec<Membership>
+}
+
+/**
+* Result of checking a permission, node is the matching node if any and source is where it
+* came from ("user" or the group name).
+*/
+pub struct Resolution<'a> {
+    pub value: bool,
+    pub node: Option<&'a Permission>,
+    pub source: Option<&'a str>
+}
+
+impl Contexts {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /**
+    * Whether something scoped to these contexts applies in current, every pair must be in it.
+    */
+    pub fn applies_in(&self, current: &Contexts) -> bool {
+        self.0.iter().all(|pair| current.0.contains(pair))
+    }
+}
+
+impl FromStr for Contexts {
+    type Err = BackendError;
+
+    /**
+    * Keys and values are lowercased and sorted by key, a key can only appear once.
+    */
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pairs: Vec<(Box<str>, Box<str>)> = vec![];
+
+        for pair in s.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or(BackendError::new("Contexts must be key=value pairs", 400))?;
+            let (key, value) = (key.trim().to_lowercase(), value.trim().to_lowercase());
+
+            if key.is_empty() || value.is_empty() || format!("{key}{value}").contains(['@', ':', '=']) {
+                return Err(BackendError::new("Invalid context", 400));
+            }
+            if pairs.iter().any(|(k, _)| k.as_ref() == key) {
+                return Err(BackendError::new("Duplicated context key", 400));
+            }
+            pairs.push((key.into(), value.into()));
+        }
+        pairs.sort();
+
+        Ok(Self(pairs))
+    }
+}
+
+impl Display for Contexts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pairs: Vec<String> = self.0.iter().map(|(k, v)| format!("{k}={v}")).collect();
+
+        write!(f, "{}", pairs.join(","))
+    }
+}
+
+/**
+* Storage key of a permission node or group membership: the name alone when it applies
+* everywhere, "{name}@{contexts}" otherwise.
+*/
+pub fn scoped_key(name: &str, contexts: &Contexts) -> String {
+    if contexts.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{name}@{contexts}")
+    }
+}
+
+pub fn parse_scoped_key(key: &str) -> Result<(&str, Contexts), BackendError> {
+    match key.split_once('@') {
+        Some((name, contexts)) => Ok((name, contexts.parse()?)),
+        None => Ok((key, Contexts::default()))
+    }
+}
+
+impl Permission {
+    pub fn storage_key(&self) -> String {
+        scoped_key(&self.perm, &self.contexts)
+    }
 }
 
 impl Group {
     pub fn new(name: &str) -> Self {
-        Self { name: name.into(), prefix: "".into(), suffix: "".into(), perms: vec![] }
+        Self { name: name.into(), prefix: "".into(), suffix: "".into(), perms: vec![], parents: vec![], weight: 0, effective_perms: vec![] }
+    }
+}
+
+/**
+* How specific a node is when matched against perm, None if it doesn't match. Matching is
+* case insensitive, "*" matches everything and "a.b.*" matches anything below "a.b" (but not
+* "a.b" itself), the more segments before the wildcard the more specific. Exact matches are
+* always the most specific.
+*/
+pub fn match_specificity(node: &str, perm: &str) -> Option<usize> {
+    if node.eq_ignore_ascii_case(perm) {
+        return Some(usize::MAX);
+    }
+    if node == "*" {
+        return Some(0);
+    }
+
+    let base = node.strip_suffix(".*")?;
+    let perm = perm.as_bytes();
+    if perm.len() > base.len() && perm[base.len()] == b'.' && perm[..base.len()].eq_ignore_ascii_case(base.as_bytes()) {
+        return Some(base.split('.').count());
+    }
+
+    None
+}
+
+/**
+* Finds the node that decides perm in a set of nodes, skipping the ones that don't apply in
+* contexts. The most specific match wins, then the one scoped to more contexts, then negated
+* nodes.
+*/
+pub fn find_node<'a>(perms: &'a [Permission], perm: &str, contexts: &Contexts) -> Option<&'a Permission> {
+    perms.iter()
+        .filter(|p| p.contexts.applies_in(contexts))
+        .filter_map(|p| match_specificity(&p.perm, perm).map(|s| (s, p)))
+        .max_by_key(|(s, p)| (*s, p.contexts.len(), !p.value))
+        .map(|(_, p)| p)
+}
+
+impl PermissionHolder {
+    /**
+    * Resolves perm in the given contexts, the user's own nodes override the groups' even if
+    * less specific, then the first group (by weight) held in those contexts with a matching
+    * effective node decides. Permissions nobody sets are denied.
+    */
+    pub fn check(&self, perm: &str, contexts: &Contexts) -> Resolution<'_> {
+        if let Some(node) = find_node(&self.perms, perm, contexts) {
+            return Resolution { value: node.value, node: Some(node), source: Some("user") };
+        }
+        for membership in self.groups.iter().filter(|m| m.contexts.applies_in(contexts)) {
+            if let Some(node) = find_node(&membership.group.effective_perms, perm, contexts) {
+                return Resolution { value: node.value, node: Some(node), source: Some(&membership.group.name) };
+            }
+        }
+
+        Resolution { value: false, node: None, source: None }
+    }
+}
+
+impl Resolution<'_> {
+    pub fn to_json(&self) -> JsonValue {
+        object! {
+            value: self.value,
+            node: self.node.map(|n| n.perm.as_ref()),
+            contexts: self.node.map(|n| n.contexts.to_string()),
+            source: self.source
+        }
+    }
+}
+
+impl Membership {
+    pub fn to_json(&self) -> JsonValue {
+        object! {
+            name: self.group.name.as_ref(),
+            contexts: self.contexts.to_string(),
+            expires_at: self.expires_at.map(|e| e.timestamp_millis())
+        }
+    }
+
+    /**
+    * Only the group name is read, the group itself isn't loaded.
+    */
+    pub fn from_json(json: &JsonValue) -> Result<Self, BackendError> {
+        Ok(Self {
+            group: Group::new(json["name"].as_str().ok_or(BackendError::new("membership.name missing", 400))?),
+            contexts: json["contexts"].as_str().unwrap_or("").parse()?,
+            expires_at: json["expires_at"].as_i64().and_then(DateTime::from_timestamp_millis)
+        })
     }
 }
 
@@ -24,14 +241,18 @@ impl SerializableJson for Permission {
     fn to_json(&self) -> json::JsonValue {
         object! {
             permission: self.perm.as_ref(),
-            value: self.value
+            value: self.value,
+            contexts: self.contexts.to_string(),
+            expires_at: self.expires_at.map(|e| e.timestamp_millis())
         }
     }
 
     fn from_json(json: &json::JsonValue) -> Result<Self, super::BackendError> where Self: Sized {
         Ok(Self {
-            perm: json["perm"].as_str().ok_or(BackendError::new("permission.perm missing", 400))?.into(),
+            perm: json["perm"].as_str().or(json["permission"].as_str()).ok_or(BackendError::new("permission.perm missing", 400))?.into(),
             value: json["value"].as_bool().ok_or(BackendError::new("permission.value missing", 400))?,
+            contexts: json["contexts"].as_str().unwrap_or("").parse()?,
+            expires_at: json["expires_at"].as_i64().and_then(DateTime::from_timestamp_millis)
         })
     }
 }
@@ -44,7 +265,10 @@ impl SerializableJson for Group {
             suffix: self.suffix.as_ref(),
             perms: JsonValue::Array(
                 self.perms.iter().map(|p| p.to_json()).collect()
-            )
+            ),
+            parents: JsonValue::Array(self.parents.iter().map(|p| p.as_ref().into()).collect()),
+            weight: self.weight,
+            effective_perms: JsonValue::Array(self.effective_perms.iter().map(|p| p.to_json()).collect())
         }
     }
 
@@ -53,7 +277,129 @@ impl SerializableJson for Group {
             name: json["name"].as_str().ok_or(BackendError::new("group.name missing", 400))?.into(),
             prefix: json["prefix"].as_str().ok_or(BackendError::new("group.prefix missing", 400))?.into(),
             suffix: json["suffix"].as_str().ok_or(BackendError::new("group.suffix missing", 400))?.into(),
-            perms: json["perms"].members().filter_map(|j| Permission::from_json(j).ok()).collect()
+            perms: json["perms"].members().filter_map(|j| Permission::from_json(j).ok()).collect(),
+            parents: json["parents"].members().filter_map(|p| p.as_str().map(|p| p.into())).collect(),
+            weight: json["weight"].as_i32().unwrap_or(0),
+            effective_perms: vec![]
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(perm: &str, value: bool, contexts: &str) -> Permission {
+        Permission { perm: perm.into(), value, contexts: contexts.parse().unwrap(), expires_at: None }
+    }
+
+    fn group(name: &str, perms: Vec<Permission>) -> Membership {
+        let mut group = Group::new(name);
+        group.effective_perms = perms;
+
+        Membership { group, contexts: Contexts::default(), expires_at: None }
+    }
+
+    #[test]
+    fn specificity() {
+        assert_eq!(match_specificity("kits.use", "KITS.USE"), Some(usize::MAX));
+        assert_eq!(match_specificity("*", "kits.use"), Some(0));
+        assert_eq!(match_specificity("kits.*", "kits.use"), Some(1));
+        assert_eq!(match_specificity("kits.use.*", "kits.use.vip"), Some(2));
+        assert_eq!(match_specificity("Kits.*", "kits.use.vip"), Some(1));
+        assert_eq!(match_specificity("kits.*", "kits"), None);
+        assert_eq!(match_specificity("kits.*", "kitsmore.use"), None);
+        assert_eq!(match_specificity("kits.use", "kits.use.vip"), None);
+    }
+
+    #[test]
+    fn most_specific_node_wins() {
+        let perms = vec![node("*", true, ""), node("kits.*", false, ""), node("kits.vip", true, "")];
+        let empty = Contexts::default();
+
+        assert!(find_node(&perms, "kits.vip", &empty).unwrap().value);
+        assert!(!find_node(&perms, "kits.pvp", &empty).unwrap().value);
+        assert!(find_node(&perms, "fly", &empty).unwrap().value);
+        assert!(find_node(&[], "fly", &empty).is_none());
+    }
+
+    #[test]
+    fn negated_nodes_win_ties() {
+        let perms = vec![node("fly", true, ""), node("fly", false, "")];
+
+        assert!(!find_node(&perms, "fly", &Contexts::default()).unwrap().value);
+    }
+
+    #[test]
+    fn user_nodes_override_groups() {
+        let holder = PermissionHolder {
+            perms: vec![node("kits.*", false, "")],
+            groups: vec![group("vip", vec![node("kits.vip", true, "")])]
+        };
+        let res = holder.check("kits.vip", &Contexts::default());
+
+        assert!(!res.value);
+        assert_eq!(res.source, Some("user"));
+    }
+
+    #[test]
+    fn first_group_with_a_match_decides() {
+        let holder = PermissionHolder {
+            perms: vec![],
+            groups: vec![group("admin", vec![node("fly", false, "")]), group("vip", vec![node("fly", true, ""), node("kits.vip", true, "")])]
+        };
+        let empty = Contexts::default();
+
+        assert!(!holder.check("fly", &empty).value);
+        assert_eq!(holder.check("fly", &empty).source, Some("admin"));
+        assert_eq!(holder.check("kits.vip", &empty).source, Some("vip"));
+
+        let unset = holder.check("build", &empty);
+        assert!(!unset.value && unset.node.is_none() && unset.source.is_none());
+    }
+
+    #[test]
+    fn contexts_are_normalized() {
+        let contexts: Contexts = " World=Nether , server=survival ".parse().unwrap();
+
+        assert_eq!(contexts.to_string(), "server=survival,world=nether");
+        assert_eq!(contexts, "world=nether,server=survival".parse().unwrap());
+        assert!("".parse::<Contexts>().unwrap().is_empty());
+        assert!(",,".parse::<Contexts>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_contexts() {
+        for invalid in ["server", "server=", "=survival", "server=a,server=b", "server=a@b", "ser:ver=a", "server=a=b"] {
+            assert!(invalid.parse::<Contexts>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn contexts_apply_in_supersets() {
+        let scoped: Contexts = "server=survival".parse().unwrap();
+
+        assert!(scoped.applies_in(&"server=survival,world=nether".parse().unwrap()));
+        assert!(!scoped.applies_in(&"server=lobby".parse().unwrap()));
+        assert!(!scoped.applies_in(&Contexts::default()));
+        assert!(Contexts::default().applies_in(&scoped));
+    }
+
+    #[test]
+    fn scoped_keys_round_trip() {
+        let contexts: Contexts = "world=nether,server=survival".parse().unwrap();
+        let key = scoped_key("vip", &contexts);
+
+        assert_eq!(key, "vip@server=survival,world=nether");
+        assert_eq!(parse_scoped_key(&key).unwrap(), ("vip", contexts));
+        assert_eq!(parse_scoped_key("vip").unwrap(), ("vip", Contexts::default()));
+    }
+
+    #[test]
+    fn scoped_nodes_only_apply_in_their_contexts() {
+        let perms = vec![node("fly", false, ""), node("fly", true, "server=creative")];
+
+        assert!(find_node(&perms, "fly", &"server=creative".parse().unwrap()).unwrap().value);
+        assert!(!find_node(&perms, "fly", &"server=survival".parse().unwrap()).unwrap().value);
+    }
+}
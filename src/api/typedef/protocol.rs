@@ -0,0 +1,632 @@
+use std::{fmt::Display, str::from_utf8};
+
+use hyper::body::Bytes;
+use json::JsonValue;
+use tokio_util::bytes::{Buf, BufMut, BytesMut};
+
+use crate::api::typedef::BackendError;
+
+/**
+* Version of the core websocket protocol, clients send theirs in the HELLO packet and are
+* disconnected if it differs. See docs/core-protocol.md for the wire format.
+*/
+pub const PROTOCOL_VERSION: u16 = 2;
+
+// Client -> backend packet types
+const PROPAGATE: u8 = 0;
+const TARGET: u8 = 1;
+const CACHE_READ: u8 = 2;
+const CACHE_WRITE: u8 = 3;
+const CACHE_DELETE: u8 = 4;
+const HELLO: u8 = 5;
+const REQUEST: u8 = 6;
+const RESPONSE: u8 = 7;
+const SUBSCRIBE: u8 = 8;
+const UNSUBSCRIBE: u8 = 9;
+const PUBLISH: u8 = 10;
+const REGISTER_SERVER: u8 = 11;
+const SERVER_STATUS: u8 = 12;
+const LIST_SERVERS: u8 = 13;
+const PLAYER_JOIN: u8 = 14;
+const PLAYER_QUIT: u8 = 15;
+
+// Backend -> client packet types
+const MESSAGE: u8 = 0;
+const CACHE_RESPONSE: u8 = 1;
+const REPORT_CREATED: u8 = 2;
+const INVALIDATION: u8 = 3;
+const WELCOME: u8 = 4;
+const ERROR: u8 = 5;
+// REQUEST and RESPONSE use the same types in both directions
+const CHANNEL_MESSAGE: u8 = 8;
+const SERVER_LIST: u8 = 9;
+const PRIVATE_MESSAGE: u8 = 10;
+
+/**
+* Codes sent in ERROR packets, the connection is closed after UnsupportedVersion,
+* HandshakeRequired, NameInUse and SessionReplaced.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    Malformed = 1,
+    UnknownPacket = 2,
+    UnsupportedVersion = 3,
+    HandshakeRequired = 4,
+    NameInUse = 5,
+    Internal = 6,
+    InvalidChannel = 7,
+    Forbidden = 8,
+    SessionReplaced = 9,
+    NotRegistered = 10
+}
+
+/**
+* Outcome of a request, sent in RESPONSE packets.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RpcStatus {
+    Ok = 0,
+    NotConnected = 1,
+    Timeout = 2
+}
+
+/**
+* Why a packet couldn't be decoded or handled, sent back to the client as an ERROR packet.
+*/
+#[derive(Debug, PartialEq)]
+pub struct PacketError {
+    pub code: ErrorCode,
+    pub msg: Box<str>
+}
+
+/**
+* Packets sent by game servers. Every multi-byte integer is big-endian and strings are
+* prefixed by their length as an u16, payloads take the rest of the frame.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientPacket {
+    Hello { version: u16 },
+    Propagate { payload: Bytes },
+    Target { target: Box<str>, payload: Bytes },
+    CacheRead { channel: Box<str>, key: Box<str> },
+    CacheWrite { channel: Box<str>, key: Box<str>, ttl_millis: u32, flags: u8, payload: Bytes },
+    CacheDelete { channel: Box<str>, key: Box<str> },
+    Request { request_id: i64, target: Box<str>, timeout_millis: u32, payload: Bytes },
+    Response { request_id: i64, payload: Bytes },
+    Subscribe { pattern: Box<str> },
+    Unsubscribe { pattern: Box<str> },
+    Publish { channel: Box<str>, payload: Bytes },
+    RegisterServer(JsonValue),
+    ServerStatus { players: u32, tps: f32 },
+    ListServers { r#type: Box<str> },
+    PlayerJoin { uuid: Box<str>, name: Box<str> },
+    PlayerQuit { uuid: Box<str> }
+}
+
+/**
+* Packets sent by the backend, same encoding rules as ClientPacket. Json payloads are utf-8
+* and take the rest of the frame.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerPacket {
+    Message { source: Box<str>, payload: Bytes },
+    CacheResponse { channel: Box<str>, key: Box<str>, payload: Option<Bytes> },
+    ReportCreated(JsonValue),
+    Invalidation(JsonValue),
+    Welcome { version: u16, name: Box<str> },
+    Error { code: ErrorCode, msg: Box<str> },
+    Request { request_id: i64, source: Box<str>, payload: Bytes },
+    Response { request_id: i64, status: RpcStatus, payload: Bytes },
+    ChannelMessage { channel: Box<str>, source: Box<str>, payload: Bytes },
+    ServerList(JsonValue),
+    PrivateMessage { sender: Box<str>, sender_name: Box<str>, recipient: Box<str>, message: Box<str> }
+}
+
+impl ErrorCode {
+    #[cfg(test)]
+    pub fn from_u16(code: u16) -> Option<Self> {
+        match code {
+            1 => Some(Self::Malformed),
+            2 => Some(Self::UnknownPacket),
+            3 => Some(Self::UnsupportedVersion),
+            4 => Some(Self::HandshakeRequired),
+            5 => Some(Self::NameInUse),
+            6 => Some(Self::Internal),
+            7 => Some(Self::InvalidChannel),
+            8 => Some(Self::Forbidden),
+            9 => Some(Self::SessionReplaced),
+            10 => Some(Self::NotRegistered),
+            _ => None
+        }
+    }
+}
+
+impl RpcStatus {
+    #[cfg(test)]
+    pub fn from_u8(status: u8) -> Option<Self> {
+        match status {
+            0 => Some(Self::Ok),
+            1 => Some(Self::NotConnected),
+            2 => Some(Self::Timeout),
+            _ => None
+        }
+    }
+}
+
+impl PacketError {
+    pub fn new(code: ErrorCode, msg: &str) -> Self {
+        Self { code, msg: msg.into() }
+    }
+
+    fn malformed(msg: &str) -> Self {
+        Self::new(ErrorCode::Malformed, msg)
+    }
+}
+
+impl Display for PacketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.msg)
+    }
+}
+
+impl From<BackendError> for PacketError {
+    fn from(value: BackendError) -> Self {
+        Self::new(ErrorCode::Internal, value.get_msg())
+    }
+}
+
+impl From<PacketError> for ServerPacket {
+    fn from(value: PacketError) -> Self {
+        Self::Error { code: value.code, msg: value.msg }
+    }
+}
+
+/**
+* Bounds checked reads over a frame, Buf getters panic on short buffers.
+*/
+struct Reader(Bytes);
+
+impl Reader {
+    fn ensure(&self, len: usize, field: &str) -> Result<(), PacketError> {
+        if self.0.remaining() < len {
+            return Err(PacketError::malformed(&format!("Packet too short, missing {field}")));
+        }
+        Ok(())
+    }
+
+    fn u8(&mut self, field: &str) -> Result<u8, PacketError> {
+        self.ensure(1, field)?;
+        Ok(self.0.get_u8())
+    }
+
+    fn u16(&mut self, field: &str) -> Result<u16, PacketError> {
+        self.ensure(2, field)?;
+        Ok(self.0.get_u16())
+    }
+
+    fn u32(&mut self, field: &str) -> Result<u32, PacketError> {
+        self.ensure(4, field)?;
+        Ok(self.0.get_u32())
+    }
+
+    fn f32(&mut self, field: &str) -> Result<f32, PacketError> {
+        self.ensure(4, field)?;
+        Ok(self.0.get_f32())
+    }
+
+    fn i64(&mut self, field: &str) -> Result<i64, PacketError> {
+        self.ensure(8, field)?;
+        Ok(self.0.get_i64())
+    }
+
+    fn string(&mut self, field: &str) -> Result<Box<str>, PacketError> {
+        let len = self.u16(field)? as usize;
+        self.ensure(len, field)?;
+
+        let bytes = self.0.split_to(len);
+        from_utf8(&bytes).map(|s| s.into()).map_err(|_| PacketError::malformed(&format!("{field} is not valid utf-8")))
+    }
+
+    #[cfg(test)]
+    fn bool(&mut self, field: &str) -> Result<bool, PacketError> {
+        match self.u8(field)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(PacketError::malformed(&format!("{field} is not a boolean")))
+        }
+    }
+
+    fn json(&mut self) -> Result<JsonValue, PacketError> {
+        let payload = self.rest();
+        let text = from_utf8(&payload).map_err(|_| PacketError::malformed("payload is not valid utf-8"))?;
+
+        json::parse(text).map_err(|_| PacketError::malformed("payload is not valid json"))
+    }
+
+    fn rest(&mut self) -> Bytes {
+        std::mem::take(&mut self.0)
+    }
+
+    /**
+    * Fixed size packets can't carry trailing bytes.
+    */
+    fn finish(&self) -> Result<(), PacketError> {
+        if self.0.has_remaining() {
+            return Err(PacketError::malformed("Trailing bytes after packet"));
+        }
+        Ok(())
+    }
+}
+
+/**
+* Strings are at most u16::MAX bytes, the backend only writes server names and short messages.
+*/
+fn put_string(buf: &mut BytesMut, s: &str) {
+    buf.put_u16(s.len() as u16);
+    buf.put_slice(s.as_bytes());
+}
+
+impl ClientPacket {
+    pub fn decode(frame: Bytes) -> Result<Self, PacketError> {
+        let mut r = Reader(frame);
+
+        let packet = match r.u8("packet type")? {
+            HELLO => Self::Hello { version: r.u16("version")? },
+            PROPAGATE => Self::Propagate { payload: r.rest() },
+            TARGET => Self::Target { target: r.string("target")?, payload: r.rest() },
+            CACHE_READ => Self::CacheRead { channel: r.string("channel")?, key: r.string("key")? },
+            CACHE_WRITE => Self::CacheWrite {
+                channel: r.string("channel")?,
+                key: r.string("key")?,
+                ttl_millis: r.u32("ttl_millis")?,
+                flags: r.u8("flags")?,
+                payload: r.rest()
+            },
+            CACHE_DELETE => Self::CacheDelete { channel: r.string("channel")?, key: r.string("key")? },
+            REQUEST => Self::Request {
+                request_id: r.i64("request_id")?,
+                target: r.string("target")?,
+                timeout_millis: r.u32("timeout_millis")?,
+                payload: r.rest()
+            },
+            RESPONSE => Self::Response { request_id: r.i64("request_id")?, payload: r.rest() },
+            SUBSCRIBE => Self::Subscribe { pattern: r.string("pattern")? },
+            UNSUBSCRIBE => Self::Unsubscribe { pattern: r.string("pattern")? },
+            PUBLISH => Self::Publish { channel: r.string("channel")?, payload: r.rest() },
+            REGISTER_SERVER => Self::RegisterServer(r.json()?),
+            SERVER_STATUS => Self::ServerStatus { players: r.u32("players")?, tps: r.f32("tps")? },
+            LIST_SERVERS => Self::ListServers { r#type: r.string("type")? },
+            PLAYER_JOIN => Self::PlayerJoin { uuid: r.string("uuid")?, name: r.string("name")? },
+            PLAYER_QUIT => Self::PlayerQuit { uuid: r.string("uuid")? },
+            other => return Err(PacketError::new(ErrorCode::UnknownPacket, &format!("Unknown packet type {other}")))
+        };
+        r.finish()?;
+
+        Ok(packet)
+    }
+
+    /**
+    * The client side of the codec, the backend itself only decodes these.
+    */
+    #[cfg(test)]
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+
+        match self {
+            Self::Hello { version } => {
+                buf.put_u8(HELLO);
+                buf.put_u16(*version);
+            },
+            Self::Propagate { payload } => {
+                buf.put_u8(PROPAGATE);
+                buf.put_slice(payload);
+            },
+            Self::Target { target, payload } => {
+                buf.put_u8(TARGET);
+                put_string(&mut buf, target);
+                buf.put_slice(payload);
+            },
+            Self::CacheRead { channel, key } => {
+                buf.put_u8(CACHE_READ);
+                put_string(&mut buf, channel);
+                put_string(&mut buf, key);
+            },
+            Self::CacheWrite { channel, key, ttl_millis, flags, payload } => {
+                buf.put_u8(CACHE_WRITE);
+                put_string(&mut buf, channel);
+                put_string(&mut buf, key);
+                buf.put_u32(*ttl_millis);
+                buf.put_u8(*flags);
+                buf.put_slice(payload);
+            },
+            Self::CacheDelete { channel, key } => {
+                buf.put_u8(CACHE_DELETE);
+                put_string(&mut buf, channel);
+                put_string(&mut buf, key);
+            },
+            Self::Request { request_id, target, timeout_millis, payload } => {
+                buf.put_u8(REQUEST);
+                buf.put_i64(*request_id);
+                put_string(&mut buf, target);
+                buf.put_u32(*timeout_millis);
+                buf.put_slice(payload);
+            },
+            Self::Response { request_id, payload } => {
+                buf.put_u8(RESPONSE);
+                buf.put_i64(*request_id);
+                buf.put_slice(payload);
+            },
+            Self::Subscribe { pattern } => {
+                buf.put_u8(SUBSCRIBE);
+                put_string(&mut buf, pattern);
+            },
+            Self::Unsubscribe { pattern } => {
+                buf.put_u8(UNSUBSCRIBE);
+                put_string(&mut buf, pattern);
+            },
+            Self::Publish { channel, payload } => {
+                buf.put_u8(PUBLISH);
+                put_string(&mut buf, channel);
+                buf.put_slice(payload);
+            },
+            Self::RegisterServer(json) => {
+                buf.put_u8(REGISTER_SERVER);
+                buf.put_slice(json.dump().as_bytes());
+            },
+            Self::ServerStatus { players, tps } => {
+                buf.put_u8(SERVER_STATUS);
+                buf.put_u32(*players);
+                buf.put_f32(*tps);
+            },
+            Self::ListServers { r#type } => {
+                buf.put_u8(LIST_SERVERS);
+                put_string(&mut buf, r#type);
+            },
+            Self::PlayerJoin { uuid, name } => {
+                buf.put_u8(PLAYER_JOIN);
+                put_string(&mut buf, uuid);
+                put_string(&mut buf, name);
+            },
+            Self::PlayerQuit { uuid } => {
+                buf.put_u8(PLAYER_QUIT);
+                put_string(&mut buf, uuid);
+            }
+        }
+
+        buf.freeze()
+    }
+}
+
+impl ServerPacket {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+
+        match self {
+            Self::Message { source, payload } => {
+                buf.put_u8(MESSAGE);
+                put_string(&mut buf, source);
+                buf.put_slice(payload);
+            },
+            Self::CacheResponse { channel, key, payload } => {
+                buf.put_u8(CACHE_RESPONSE);
+                put_string(&mut buf, channel);
+                put_string(&mut buf, key);
+                buf.put_u8(payload.is_some() as u8);
+                if let Some(payload) = payload {
+                    buf.put_slice(payload);
+                }
+            },
+            Self::ReportCreated(json) => {
+                buf.put_u8(REPORT_CREATED);
+                buf.put_slice(json.dump().as_bytes());
+            },
+            Self::Invalidation(json) => {
+                buf.put_u8(INVALIDATION);
+                buf.put_slice(json.dump().as_bytes());
+            },
+            Self::Welcome { version, name } => {
+                buf.put_u8(WELCOME);
+                buf.put_u16(*version);
+                put_string(&mut buf, name);
+            },
+            Self::Error { code, msg } => {
+                buf.put_u8(ERROR);
+                buf.put_u16(*code as u16);
+                put_string(&mut buf, msg);
+            },
+            Self::Request { request_id, source, payload } => {
+                buf.put_u8(REQUEST);
+                buf.put_i64(*request_id);
+                put_string(&mut buf, source);
+                buf.put_slice(payload);
+            },
+            Self::Response { request_id, status, payload } => {
+                buf.put_u8(RESPONSE);
+                buf.put_i64(*request_id);
+                buf.put_u8(*status as u8);
+                buf.put_slice(payload);
+            },
+            Self::ChannelMessage { channel, source, payload } => {
+                buf.put_u8(CHANNEL_MESSAGE);
+                put_string(&mut buf, channel);
+                put_string(&mut buf, source);
+                buf.put_slice(payload);
+            },
+            Self::ServerList(json) => {
+                buf.put_u8(SERVER_LIST);
+                buf.put_slice(json.dump().as_bytes());
+            },
+            Self::PrivateMessage { sender, sender_name, recipient, message } => {
+                buf.put_u8(PRIVATE_MESSAGE);
+                put_string(&mut buf, sender);
+                put_string(&mut buf, sender_name);
+                put_string(&mut buf, recipient);
+                put_string(&mut buf, message);
+            }
+        }
+
+        buf.freeze()
+    }
+
+    /**
+    * The client side of the codec, the backend itself only encodes these.
+    */
+    #[cfg(test)]
+    pub fn decode(frame: Bytes) -> Result<Self, PacketError> {
+        let mut r = Reader(frame);
+
+        let packet = match r.u8("packet type")? {
+            MESSAGE => Self::Message { source: r.string("source")?, payload: r.rest() },
+            CACHE_RESPONSE => {
+                let channel = r.string("channel")?;
+                let key = r.string("key")?;
+                let found = r.bool("found")?;
+
+                Self::CacheResponse { channel, key, payload: found.then(|| r.rest()) }
+            },
+            REPORT_CREATED => Self::ReportCreated(r.json()?),
+            INVALIDATION => Self::Invalidation(r.json()?),
+            WELCOME => Self::Welcome { version: r.u16("version")?, name: r.string("name")? },
+            ERROR => {
+                let code = r.u16("code")?;
+
+                Self::Error {
+                    code: ErrorCode::from_u16(code).ok_or(PacketError::malformed(&format!("Unknown error code {code}")))?,
+                    msg: r.string("msg")?
+                }
+            },
+            REQUEST => Self::Request { request_id: r.i64("request_id")?, source: r.string("source")?, payload: r.rest() },
+            RESPONSE => {
+                let request_id = r.i64("request_id")?;
+                let status = r.u8("status")?;
+
+                Self::Response {
+                    request_id,
+                    status: RpcStatus::from_u8(status).ok_or(PacketError::malformed(&format!("Unknown rpc status {status}")))?,
+                    payload: r.rest()
+                }
+            },
+            CHANNEL_MESSAGE => Self::ChannelMessage { channel: r.string("channel")?, source: r.string("source")?, payload: r.rest() },
+            SERVER_LIST => Self::ServerList(r.json()?),
+            PRIVATE_MESSAGE => Self::PrivateMessage {
+                sender: r.string("sender")?,
+                sender_name: r.string("sender_name")?,
+                recipient: r.string("recipient")?,
+                message: r.string("message")?
+            },
+            other => return Err(PacketError::new(ErrorCode::UnknownPacket, &format!("Unknown packet type {other}")))
+        };
+        r.finish()?;
+
+        Ok(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use json::object;
+
+    use super::*;
+
+    fn client_packets() -> Vec<ClientPacket> {
+        vec![
+            ClientPacket::Hello { version: PROTOCOL_VERSION },
+            ClientPacket::Propagate { payload: Bytes::from_static(b"hello") },
+            ClientPacket::Propagate { payload: Bytes::new() },
+            ClientPacket::Target { target: "lobby-1".into(), payload: Bytes::from_static(&[1, 2, 3]) },
+            ClientPacket::CacheRead { channel: "parties".into(), key: "leader:notch".into() },
+            ClientPacket::CacheWrite { channel: "parties".into(), key: "leader:notch".into(), ttl_millis: 60_000, flags: 3, payload: Bytes::from_static(b"data") },
+            ClientPacket::CacheDelete { channel: "parties".into(), key: "leader:notch".into() },
+            ClientPacket::Request { request_id: 9, target: "practice".into(), timeout_millis: 2_000, payload: Bytes::from_static(b"is_online") },
+            ClientPacket::Response { request_id: i64::MIN, payload: Bytes::new() },
+            ClientPacket::Subscribe { pattern: "parties.*".into() },
+            ClientPacket::Unsubscribe { pattern: "parties.*".into() },
+            ClientPacket::Publish { channel: "parties.invites".into(), payload: Bytes::from_static(b"invite") },
+            ClientPacket::RegisterServer(object! { type: "lobby", region: "eu", capacity: 200 }),
+            ClientPacket::ServerStatus { players: 57, tps: 19.5 },
+            ClientPacket::ListServers { r#type: "".into() },
+            ClientPacket::PlayerJoin { uuid: "069a79f4-44e9-4726-a5be-fca90e38aaf5".into(), name: "Notch".into() },
+            ClientPacket::PlayerQuit { uuid: "069a79f4-44e9-4726-a5be-fca90e38aaf5".into() }
+        ]
+    }
+
+    fn server_packets() -> Vec<ServerPacket> {
+        vec![
+            ServerPacket::Message { source: "survival".into(), payload: Bytes::from_static(b"payload") },
+            ServerPacket::CacheResponse { channel: "parties".into(), key: "leader:notch".into(), payload: Some(Bytes::from_static(b"cached")) },
+            ServerPacket::CacheResponse { channel: "parties".into(), key: "leader:notch".into(), payload: None },
+            ServerPacket::ReportCreated(object! { id: 1, reason: "cheating" }),
+            ServerPacket::Invalidation(object! { event: "group_updated", group: "admin" }),
+            ServerPacket::Welcome { version: PROTOCOL_VERSION, name: "lobby-1".into() },
+            ServerPacket::Error { code: ErrorCode::UnknownPacket, msg: "Unknown packet type 9".into() },
+            ServerPacket::Request { request_id: 12, source: "lobby-1".into(), payload: Bytes::from_static(b"is_online") },
+            ServerPacket::Response { request_id: 9, status: RpcStatus::Ok, payload: Bytes::from_static(&[1]) },
+            ServerPacket::Response { request_id: 9, status: RpcStatus::Timeout, payload: Bytes::new() },
+            ServerPacket::ChannelMessage { channel: "parties.invites".into(), source: "lobby-1".into(), payload: Bytes::from_static(b"invite") },
+            ServerPacket::ServerList(json::array![object! { name: "lobby-1", type: "lobby", players: 57 }]),
+            ServerPacket::PrivateMessage {
+                sender: "069a79f4-44e9-4726-a5be-fca90e38aaf5".into(),
+                sender_name: "Notch".into(),
+                recipient: "853c80ef-3c37-49fd-aa49-938b674adae6".into(),
+                message: "gg".into()
+            }
+        ]
+    }
+
+    #[test]
+    fn client_packets_round_trip() {
+        for packet in client_packets() {
+            assert_eq!(ClientPacket::decode(packet.encode()), Ok(packet));
+        }
+    }
+
+    #[test]
+    fn server_packets_round_trip() {
+        for packet in server_packets() {
+            assert_eq!(ServerPacket::decode(packet.encode()), Ok(packet));
+        }
+    }
+
+    #[test]
+    fn truncated_packets_are_malformed() {
+        for packet in client_packets() {
+            let frame = packet.encode();
+
+            // Payload packets accept any length once their fixed fields are read
+            let min = match packet {
+                ClientPacket::Propagate { .. } => 1,
+                ClientPacket::Target { ref target, .. } => 3 + target.len(),
+                ClientPacket::CacheWrite { ref channel, ref key, .. } => 10 + channel.len() + key.len(),
+                ClientPacket::Request { ref target, .. } => 15 + target.len(),
+                ClientPacket::Response { .. } => 9,
+                ClientPacket::Publish { ref channel, .. } => 3 + channel.len(),
+                ClientPacket::RegisterServer(_) => 1,
+                _ => frame.len()
+            };
+            for len in 0..min {
+                let err = ClientPacket::decode(frame.slice(..len)).unwrap_err();
+                assert_eq!(err.code, ErrorCode::Malformed, "{packet:?} truncated to {len}");
+            }
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_malformed() {
+        let mut frame = BytesMut::from(ClientPacket::CacheDelete { channel: "parties".into(), key: "a".into() }.encode().as_ref());
+        frame.put_u8(0);
+
+        assert_eq!(ClientPacket::decode(frame.freeze()).unwrap_err().code, ErrorCode::Malformed);
+    }
+
+    #[test]
+    fn unknown_packet_type() {
+        assert_eq!(ClientPacket::decode(Bytes::from_static(&[200, 1, 2])).unwrap_err().code, ErrorCode::UnknownPacket);
+    }
+
+    #[test]
+    fn invalid_utf8_is_malformed() {
+        let frame = Bytes::from_static(&[TARGET, 0, 2, 0xff, 0xfe]);
+
+        assert_eq!(ClientPacket::decode(frame).unwrap_err().code, ErrorCode::Malformed);
+    }
+}
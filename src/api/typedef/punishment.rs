@@ -3,7 +3,7 @@ use std::{cmp::Ordering, str::FromStr};
 use chrono::{DateTime, Utc};
 use json::{JsonValue, object};
 
-use crate::api::typedef::{BackendError, jsonutils::SerializableJson};
+use crate::api::typedef::{BackendError, jsonutils::SerializableJson, network::Subnet};
 
 pub struct Punishment {
     pub id: u64,
@@ -13,13 +13,37 @@ pub struct Punishment {
     pub expiration_date: Option<DateTime<Utc>>,
     pub reason: Box<str>,
     pub alsoip: bool,
+    pub subnet: Option<Subnet>,
     pub allow_chat: bool,
     pub allow_ranked: bool,
     pub allow_unranked: bool,
-    pub allow_join_minigames: bool
+    pub allow_join_minigames: bool,
+    pub issuer: Option<Box<str>>,
+    pub revoked_by: Option<Box<str>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub revoke_reason: Option<Box<str>>,
+    pub template: Option<Box<str>>
 }
 
+pub const BAN_TYPE: &str = "ban";
+pub const MUTE_TYPE: &str = "mute";
+
 impl Punishment {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        !self.is_revoked() && self.expiration_date.is_none_or(|date| date > now)
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+
+    /**
+    * Bans are the only punishments that prevent joining the network.
+    */
+    pub fn is_ban(&self) -> bool {
+        self.r#type.eq_ignore_ascii_case(BAN_TYPE)
+    }
+
     pub fn get_priority(&self) -> u8 {
         !self.alsoip as u8 +
         !self.allow_chat as u8 +
@@ -42,10 +66,22 @@ impl SerializableJson for Punishment {
             },
             reason: self.reason.as_ref(),
             alsoip: self.alsoip,
+            subnet: match &self.subnet {
+                Some(subnet) => JsonValue::String(subnet.to_string()),
+                _ => JsonValue::Null
+            },
             allow_chat: self.allow_chat,
             allow_ranked: self.allow_ranked,
             allow_unranked: self.allow_unranked,
             allow_join_minigames: self.allow_join_minigames,
+            issuer: self.issuer.as_deref(),
+            revoked_by: self.revoked_by.as_deref(),
+            revoked_at: match self.revoked_at {
+                Some(date) => JsonValue::String(date.to_string()),
+                _ => JsonValue::Null
+            },
+            revoke_reason: self.revoke_reason.as_deref(),
+            template: self.template.as_deref()
         }
     }
 
@@ -65,10 +101,16 @@ impl SerializableJson for Punishment {
             expiration_date: expiration_date,
             reason: json["reason"].as_str().unwrap_or("Unspecified").into(),
             alsoip: json["alsoip"].as_bool().ok_or(BackendError::new("punishment.alsoip missing", 400))?,
+            subnet: json["subnet"].as_str().and_then(|s| Subnet::from_str(s).ok()),
             allow_chat: json["allow_chat"].as_bool().ok_or(BackendError::new("punishment.allow_chat missing", 400))?,
             allow_ranked: json["allow_ranked"].as_bool().ok_or(BackendError::new("punishment.allow_ranked missing", 400))?,
             allow_unranked: json["allow_unranked"].as_bool().ok_or(BackendError::new("punishment.allow_unranked missing", 400))?,
-            allow_join_minigames: json["allow_join_minigames"].as_bool().ok_or(BackendError::new("punishment.allow_join_minigames missing", 400))?
+            allow_join_minigames: json["allow_join_minigames"].as_bool().ok_or(BackendError::new("punishment.allow_join_minigames missing", 400))?,
+            issuer: json["issuer"].as_str().map(|s| s.into()),
+            revoked_by: json["revoked_by"].as_str().map(|s| s.into()),
+            revoked_at: json["revoked_at"].as_str().and_then(|s| DateTime::from_str(s).ok()),
+            revoke_reason: json["revoke_reason"].as_str().map(|s| s.into()),
+            template: json["template"].as_str().map(|s| s.into())
         })
     }
 }
@@ -77,7 +119,7 @@ impl Eq for Punishment {}
 
 impl PartialEq for Punishment {
     fn eq(&self, other: &Self) -> bool {
-        self.id == other.id && self.creation_date == other.creation_date && self.expiration_date == other.expiration_date && self.reason == other.reason && self.alsoip == other.alsoip && self.allow_chat == other.allow_chat && self.allow_ranked == other.allow_ranked && self.allow_unranked == other.allow_unranked && self.allow_join_minigames == other.allow_join_minigames
+        self.id == other.id && self.creation_date == other.creation_date && self.expiration_date == other.expiration_date && self.reason == other.reason && self.alsoip == other.alsoip && self.allow_chat == other.allow_chat && self.allow_ranked == other.allow_ranked && self.allow_unranked == other.allow_unranked && self.allow_join_minigames == other.allow_join_minigames && self.revoked_at == other.revoked_at
     }
 }
 
@@ -101,8 +143,156 @@ impl Ord for Punishment {
             return Ordering::Equal;
         }
 
-        let time = Utc::now().timestamp_millis() - self.expiration_date.unwrap().timestamp_millis();
-        let otime = Utc::now().timestamp_millis() - other.expiration_date.unwrap().timestamp_millis();
-        time.cmp(&otime)
+        // The one expiring later is the lesser
+        other.expiration_date.cmp(&self.expiration_date)
+    }
+}
+
+/**
+* The effective state of a player computed from their active punishments at a given time.
+*/
+pub struct Restrictions<'a> {
+    pub can_join: bool,
+    pub can_chat: bool,
+    pub can_play_ranked: bool,
+    pub can_play_unranked: bool,
+    pub can_join_minigames: bool,
+    pub earliest_expiry: Option<DateTime<Utc>>,
+    pub governing: Option<&'a Punishment>
+}
+
+impl<'a> Restrictions<'a> {
+    /**
+    * Bans restrict everything, other punishments restrict what their allow flags deny. The
+    * governing punishment is the most severe active one: bans first, then by priority, then the
+    * one lasting longer.
+    */
+    pub fn evaluate(punishments: &'a [Punishment], now: DateTime<Utc>) -> Self {
+        let mut res = Self {
+            can_join: true, can_chat: true, can_play_ranked: true,
+            can_play_unranked: true, can_join_minigames: true,
+            earliest_expiry: None, governing: None
+        };
+
+        for pun in punishments.iter().filter(|p| p.is_active(now)) {
+            let ban = pun.is_ban();
+
+            res.can_join &= !ban;
+            res.can_chat &= !ban && pun.allow_chat;
+            res.can_play_ranked &= !ban && pun.allow_ranked;
+            res.can_play_unranked &= !ban && pun.allow_unranked;
+            res.can_join_minigames &= !ban && pun.allow_join_minigames;
+
+            if let Some(date) = pun.expiration_date {
+                res.earliest_expiry = Some(res.earliest_expiry.map_or(date, |e| e.min(date)));
+            }
+            if res.governing.is_none_or(|g| severity(pun, g) == Ordering::Greater) {
+                res.governing = Some(pun);
+            }
+        }
+
+        res
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        object! {
+            can_join: self.can_join,
+            can_chat: self.can_chat,
+            can_play_ranked: self.can_play_ranked,
+            can_play_unranked: self.can_play_unranked,
+            can_join_minigames: self.can_join_minigames,
+            earliest_expiry: self.earliest_expiry.map(|d| d.timestamp_millis()),
+            governing_punishment: match self.governing {
+                Some(pun) => pun.to_json(),
+                _ => JsonValue::Null
+            }
+        }
+    }
+}
+
+fn severity(a: &Punishment, b: &Punishment) -> Ordering {
+    a.is_ban().cmp(&b.is_ban())
+        .then(a.get_priority().cmp(&b.get_priority()))
+        .then(match (a.expiration_date, b.expiration_date) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(x), Some(y)) => x.cmp(&y)
+        })
+        .then(a.creation_date.cmp(&b.creation_date))
+}
+
+#[cfg(test)]
+impl Punishment {
+    /**
+    * A punishment issued an hour before now that ends the given amount of hours after it, None
+    * makes it permanent. Mutes only deny chat, other types deny nothing.
+    */
+    pub fn test_default(id: u64, r#type: &str, now: DateTime<Utc>, hours: Option<i64>) -> Self {
+        Self {
+            id, title: "".into(), r#type: r#type.into(),
+            creation_date: now - chrono::TimeDelta::hours(1),
+            expiration_date: hours.map(|h| now + chrono::TimeDelta::hours(h)),
+            reason: "".into(), alsoip: false, subnet: None,
+            allow_chat: r#type != MUTE_TYPE,
+            allow_ranked: true, allow_unranked: true, allow_join_minigames: true,
+            issuer: None, revoked_by: None, revoked_at: None, revoke_reason: None, template: None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_punishments_allow_everything() {
+        let res = Restrictions::evaluate(&[], Utc::now());
+
+        assert!(res.can_join && res.can_chat && res.can_play_ranked && res.can_play_unranked && res.can_join_minigames);
+        assert!(res.governing.is_none() && res.earliest_expiry.is_none());
+    }
+
+    #[test]
+    fn mutes_only_deny_chat() {
+        let now = Utc::now();
+        let puns = [Punishment::test_default(1, MUTE_TYPE, now, Some(2))];
+        let res = Restrictions::evaluate(&puns, now);
+
+        assert!(!res.can_chat);
+        assert!(res.can_join && res.can_play_ranked);
+        assert_eq!(res.governing.map(|p| p.id), Some(1));
+        assert_eq!(res.earliest_expiry, puns[0].expiration_date);
+    }
+
+    #[test]
+    fn bans_deny_everything_and_govern() {
+        let now = Utc::now();
+        let puns = [Punishment::test_default(1, MUTE_TYPE, now, None), Punishment::test_default(2, BAN_TYPE, now, Some(5)), Punishment::test_default(3, MUTE_TYPE, now, Some(1))];
+        let res = Restrictions::evaluate(&puns, now);
+
+        assert!(!res.can_join && !res.can_chat && !res.can_play_ranked && !res.can_play_unranked && !res.can_join_minigames);
+        assert_eq!(res.governing.map(|p| p.id), Some(2));
+        assert_eq!(res.earliest_expiry, puns[2].expiration_date);
+    }
+
+    #[test]
+    fn inactive_punishments_are_ignored() {
+        let now = Utc::now();
+        let mut revoked = Punishment::test_default(1, BAN_TYPE, now, None);
+        revoked.revoked_at = Some(now);
+        let puns = [revoked, Punishment::test_default(2, BAN_TYPE, now, Some(-1))];
+        let res = Restrictions::evaluate(&puns, now);
+
+        assert!(res.can_join && res.can_chat);
+        assert!(res.governing.is_none());
+    }
+
+    #[test]
+    fn longer_punishments_govern_equal_ones() {
+        let now = Utc::now();
+        let puns = [Punishment::test_default(1, MUTE_TYPE, now, Some(1)), Punishment::test_default(2, MUTE_TYPE, now, None), Punishment::test_default(3, MUTE_TYPE, now, Some(3))];
+
+        assert_eq!(Restrictions::evaluate(&puns, now).governing.map(|p| p.id), Some(2));
     }
 }
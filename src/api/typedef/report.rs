@@ -0,0 +1,91 @@
+use chrono::{DateTime, Utc};
+use json::{JsonValue, object};
+
+use crate::api::typedef::{BackendError, jsonutils::SerializableJson};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ReportStatus {
+    Open,
+    Claimed,
+    Resolved
+}
+
+/**
+* A player report sent by a game server.
+*/
+pub struct Report {
+    pub id: u64,
+    pub reporter: Box<str>,
+    pub target: Box<str>,
+    pub reason: Box<str>,
+    pub category: Box<str>,
+    pub server: Box<str>,
+    pub evidence: Vec<Box<str>>,
+    pub status: ReportStatus,
+    pub claimed_by: Option<Box<str>>,
+    pub resolution: Option<Box<str>>,
+    pub punishment_id: Option<u64>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>
+}
+
+impl ReportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportStatus::Open => "open",
+            ReportStatus::Claimed => "claimed",
+            ReportStatus::Resolved => "resolved"
+        }
+    }
+}
+
+impl TryFrom<&str> for ReportStatus {
+    type Error = BackendError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "open" => Ok(ReportStatus::Open),
+            "claimed" => Ok(ReportStatus::Claimed),
+            "resolved" => Ok(ReportStatus::Resolved),
+            _ => Err(BackendError::new("Unknown report status", 400))
+        }
+    }
+}
+
+impl SerializableJson for Report {
+    fn to_json(&self) -> JsonValue {
+        object! {
+            id: self.id,
+            reporter: self.reporter.as_ref(),
+            target: self.target.as_ref(),
+            reason: self.reason.as_ref(),
+            category: self.category.as_ref(),
+            server: self.server.as_ref(),
+            evidence: JsonValue::Array(self.evidence.iter().map(|e| e.as_ref().into()).collect()),
+            status: self.status.as_str(),
+            claimed_by: self.claimed_by.as_deref(),
+            resolution: self.resolution.as_deref(),
+            punishment_id: self.punishment_id,
+            created_at: self.created_at.timestamp_millis(),
+            updated_at: self.updated_at.timestamp_millis()
+        }
+    }
+
+    fn from_json(json: &JsonValue) -> Result<Self, BackendError> where Self: Sized {
+        Ok(Self {
+            id: json["id"].as_u64().ok_or(BackendError::new("report.id missing", 400))?,
+            reporter: json["reporter"].as_str().ok_or(BackendError::new("report.reporter missing", 400))?.into(),
+            target: json["target"].as_str().ok_or(BackendError::new("report.target missing", 400))?.into(),
+            reason: json["reason"].as_str().ok_or(BackendError::new("report.reason missing", 400))?.into(),
+            category: json["category"].as_str().unwrap_or("other").into(),
+            server: json["server"].as_str().unwrap_or("unknown").into(),
+            evidence: json["evidence"].members().filter_map(|e| e.as_str().map(|s| s.into())).collect(),
+            status: json["status"].as_str().ok_or(BackendError::new("report.status missing", 400))?.try_into()?,
+            claimed_by: json["claimed_by"].as_str().map(|s| s.into()),
+            resolution: json["resolution"].as_str().map(|s| s.into()),
+            punishment_id: json["punishment_id"].as_u64(),
+            created_at: json["created_at"].as_i64().and_then(DateTime::from_timestamp_millis).unwrap_or(Utc::now()),
+            updated_at: json["updated_at"].as_i64().and_then(DateTime::from_timestamp_millis).unwrap_or(Utc::now())
+        })
+    }
+}
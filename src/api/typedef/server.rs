@@ -0,0 +1,56 @@
+use chrono::{DateTime, Utc};
+use json::{JsonValue, object};
+
+use crate::api::typedef::{BackendError, jsonutils::SerializableJson};
+
+/**
+* A game server connected to the core websocket, registered with its metadata. players and
+* tps come from its periodic status packets.
+*/
+#[derive(Clone)]
+pub struct ServerInfo {
+    pub name: Box<str>,
+    pub r#type: Box<str>,
+    pub region: Box<str>,
+    pub capacity: u32,
+    pub address: Option<Box<str>>,
+    pub version: Box<str>,
+    pub players: u32,
+    pub tps: f32,
+    pub registered_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>
+}
+
+impl SerializableJson for ServerInfo {
+    fn to_json(&self) -> JsonValue {
+        object! {
+            name: self.name.as_ref(),
+            type: self.r#type.as_ref(),
+            region: self.region.as_ref(),
+            capacity: self.capacity,
+            address: self.address.as_deref(),
+            version: self.version.as_ref(),
+            players: self.players,
+            tps: self.tps,
+            registered_at: self.registered_at.timestamp_millis(),
+            updated_at: self.updated_at.timestamp_millis()
+        }
+    }
+
+    fn from_json(json: &JsonValue) -> Result<Self, BackendError> where Self: Sized {
+        let now = Utc::now();
+
+        Ok(Self {
+            name: json["name"].as_str().ok_or(BackendError::new("server.name missing", 400))?.into(),
+            r#type: json["type"].as_str().ok_or(BackendError::new("server.type missing", 400))?.into(),
+            region: json["region"].as_str().unwrap_or("default").into(),
+            capacity: json["capacity"].as_u32().unwrap_or(0),
+            address: json["address"].as_str().map(|a| a.into()),
+            version: json["version"].as_str().unwrap_or("unknown").into(),
+            players: json["players"].as_u32().unwrap_or(0),
+            tps: json["tps"].as_f32().unwrap_or(20.0),
+            registered_at: json["registered_at"].as_i64().and_then(DateTime::from_timestamp_millis).unwrap_or(now),
+            updated_at: json["updated_at"].as_i64().and_then(DateTime::from_timestamp_millis).unwrap_or(now)
+        })
+    }
+}
@@ -7,7 +7,7 @@ use crate::api::control::storage::query::{get_default_group_name, get_group_full
 use crate::api::typedef::BackendError;
 use crate::api::typedef::jsonutils::SerializableJson;
 use crate::api::typedef::mailing::{Mail, get_mails_from_json};
-use crate::api::typedef::permissions::{Group, Permission};
+use crate::api::typedef::permissions::{Contexts, Group, Membership, Permission};
 use crate::api::typedef::punishment::Punishment;
 
 #[derive(Clone)]
@@ -22,6 +22,17 @@ pub struct UserMapping {
     pub name: Box<str>
 }
 
+/**
+* An account that shares addresses with another one.
+*/
+pub struct AltAccount {
+    pub uuid: Box<str>,
+    pub name: Box<str>,
+    pub shared_ips: u32,
+    pub last_seen: DateTime<Utc>,
+    pub punishments: Vec<Punishment>
+}
+
 pub struct User {
     pub uuid: Box<str>,
     pub name: Box<str>,
@@ -40,7 +51,7 @@ pub struct User {
     pub inbox: Vec<Box<dyn Mail>>,
     pub punishments: Vec<Punishment>,
     pub perms: Vec<Permission>,
-    pub group: Option<Group>
+    pub groups: Vec<Membership>
 }
 
 impl From<u8> for PmsMode {
@@ -108,10 +119,10 @@ impl SerializableJson for User {
                     .map(|p| p.to_json()).collect()
             ),
             perms: JsonValue::Array(self.perms.iter().map(|p| p.to_json()).collect()),
-            group: match &self.group {
-                Some(g) => Some(g.name.as_ref()),
-                _ => None
-            }
+            group: self.primary_group().map(|g| g.name.as_ref()),
+            groups: JsonValue::Array(self.groups.iter().map(|m| m.to_json()).collect()),
+            group_prefix: self.display_prefix(),
+            group_suffix: self.display_suffix()
         }
     }
 
@@ -133,19 +144,31 @@ impl SerializableJson for User {
         let inbox: Vec<Box<dyn Mail>> = get_mails_from_json(&json["inbox"]);
         let punishments: Vec<Punishment> = json["punishments"].members().filter_map(|json| Punishment::from_json(json).ok()).collect();
         let perms: Vec<Permission> = json["perms"].members().filter_map(|json| Permission::from_json(json).ok()).collect();
-        let group: Option<Group> = Group::from_json(&json["group"]).ok();
+        let groups: Vec<Membership> = json["groups"].members().filter_map(|json| Membership::from_json(json).ok()).collect();
 
         Ok(Self {
             uuid, name, email, chat, pms, suffix, lang, scoreboard, coins,
             friend_reqs, dnd, created_at: DateTime::from_timestamp_millis(created_at as i64).unwrap_or(Utc::now()),
-            friends, ignores, inbox, punishments, perms, group
+            friends, ignores, inbox, punishments, perms, groups
         })
     }
 }
 
+impl AltAccount {
+    pub fn to_json(&self) -> JsonValue {
+        object! {
+            uuid: self.uuid.as_ref(),
+            name: self.name.as_ref(),
+            shared_ips: self.shared_ips,
+            last_seen: self.last_seen.timestamp_millis(),
+            punishments: JsonValue::Array(self.punishments.iter().map(|p| p.to_json()).collect())
+        }
+    }
+}
+
 impl User {
     pub fn to_json_reduced(&self) -> JsonValue {
-        let group_name = self.group.as_ref().map(|g| g.name.as_ref()).unwrap_or("none");
+        let group_name = self.primary_group().map(|g| g.name.as_ref()).unwrap_or("none");
 
         object! {
             uuid: self.uuid.as_ref(),
@@ -153,10 +176,46 @@ impl User {
             suffix: self.suffix.as_ref(),
             created_at: self.created_at.timestamp_millis(),
             punishments: array![self.punishments.iter().map(|pun| pun.to_json()).collect::<Vec<JsonValue>>()],
-            group: group_name
+            group: group_name,
+            group_prefix: self.display_prefix()
+        }
+    }
+
+    /**
+    * The highest weight group the user holds, groups are kept sorted by weight.
+    */
+    pub fn primary_group(&self) -> Option<&Group> {
+        self.groups.first().map(|m| &m.group)
+    }
+
+    /**
+    * Prefix of the highest weight group that has one.
+    */
+    pub fn display_prefix(&self) -> &str {
+        self.groups.iter().map(|m| m.group.prefix.as_ref()).find(|p| !p.is_empty()).unwrap_or("")
+    }
+
+    /**
+    * Suffix of the highest weight group that has one.
+    */
+    pub fn display_suffix(&self) -> &str {
+        self.groups.iter().map(|m| m.group.suffix.as_ref()).find(|s| !s.is_empty()).unwrap_or("")
+    }
+
+    /**
+    * A user with the default settings and no groups.
+    */
+    pub fn new(uuid: &str, name: &str) -> Self {
+        Self {
+            uuid: uuid.into(), name: name.into(), email: None, chat: true, pms: PmsMode::PmsEnabled,
+            suffix: "".into(), lang: "en".into(), scoreboard: true, coins: 0, friend_reqs: true, dnd: false,
+            created_at: Utc::now(), friends: vec![], ignores: vec![], inbox: vec![], punishments: vec![], perms: vec![], groups: vec![]
         }
     }
 
+    /**
+    * A new user holding the default group, if there is one.
+    */
     pub fn new_default(uuid: &str, name: &str) -> Self {
         let group_default = match get_default_group_name() {
             Ok(Some(group_name)) => {
@@ -171,12 +230,10 @@ impl User {
             }
             _ => None,
         };
-        
 
         Self {
-            uuid: uuid.into(), name: name.into(), email: None, chat: true, pms: PmsMode::PmsEnabled,
-            suffix: "".into(), lang: "en".into(), scoreboard: true, coins: 0, friend_reqs: true, dnd: false,
-            created_at: Utc::now(), friends: vec![], ignores: vec![], inbox: vec![], punishments: vec![], perms: vec![], group: group_default
+            groups: group_default.into_iter().map(|group| Membership { group, contexts: Contexts::default(), expires_at: None }).collect(),
+            ..Self::new(uuid, name)
         }
     }
 }
@@ -49,26 +49,58 @@ pub async fn get_body_str(http: HttpTransaction) -> Result<String, BackendError>
 }
 
 pub fn get_body_url_args(req: &Request<Incoming>) -> Result<HashMap<Box<str>, Box<str>>, BackendError> {
-    let mut map: HashMap<Box<str>, Box<str>> = HashMap::new();
-
     let bodyopt = req.uri().query();
     if bodyopt.is_none() {
         return Err(BackendError::new("No query found", 400));
     }
 
-    let body = bodyopt.unwrap();
+    parse_url_args(bodyopt.unwrap())
+}
 
-    let pairs = body.split('&');
-    for pair in pairs {
-        let split: Vec<&str> = pair.split('=').collect();
-        if split.len() != 2 {
-            return Err(BackendError::new("Failed to parse url query (malformed url)", 400));
-        }
-        map.insert(split[0].into(), split[1].replace('+', " ").into());
+/**
+* Like get_body_url_args, for endpoints whose params are all optional: no query means no args.
+*/
+pub fn get_optional_url_args(req: &Request<Incoming>) -> Result<HashMap<Box<str>, Box<str>>, BackendError> {
+    match req.uri().query() {
+        Some(query) => parse_url_args(query),
+        None => Ok(HashMap::new())
+    }
+}
+
+/**
+* Parses "key=value&key=value", each pair is split on its first '=' so values can hold more of
+* them (context=server=survival), keys and values are percent-decoded.
+*/
+pub fn parse_url_args(query: &str) -> Result<HashMap<Box<str>, Box<str>>, BackendError> {
+    let mut map: HashMap<Box<str>, Box<str>> = HashMap::new();
+
+    for pair in query.split('&') {
+        let (key, value) = pair.split_once('=').ok_or(BackendError::new("Failed to parse url query (malformed url)", 400))?;
+        map.insert(percent_decode(key)?, percent_decode(value)?);
     }
     Ok(map)
 }
 
+fn percent_decode(str: &str) -> Result<Box<str>, BackendError> {
+    let malformed = || BackendError::new("Failed to parse url query (malformed escape)", 400);
+    let mut bytes = Vec::with_capacity(str.len());
+
+    let mut iter = str.bytes();
+    while let Some(byte) = iter.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [iter.next().ok_or_else(malformed)?, iter.next().ok_or_else(malformed)?];
+                let hex = std::str::from_utf8(&hex).map_err(|_| malformed())?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| malformed())?);
+            },
+            _ => bytes.push(byte)
+        }
+    }
+
+    Ok(String::from_utf8(bytes).map_err(|_| malformed())?.into())
+}
+
 pub async fn get_body_json(http: HttpTransaction) -> Result<JsonValue, BackendError> {
     let body_str = get_body_str(http).await?;
     let json = json::parse(body_str.as_str());
@@ -91,3 +123,35 @@ pub fn unsafe_random_i32() -> i32 {
     let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
     nanos.wrapping_mul(1664525).wrapping_add(1013904223) as i32
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::api::typedef::permissions::Contexts;
+
+    use super::*;
+
+    #[test]
+    fn has_permission_context_is_parsed() {
+        let expected: Contexts = "server=survival,world=nether".parse().unwrap();
+
+        for query in ["uuid=a&perm=fly&context=server=survival,world=nether", "uuid=a&perm=fly&context=server%3Dsurvival%2Cworld%3dnether"] {
+            let args = parse_url_args(query).unwrap();
+            let contexts: Contexts = args.get("context").unwrap().parse().unwrap();
+
+            assert_eq!(args.get("perm").map(|p| p.as_ref()), Some("fly"));
+            assert_eq!(contexts, expected);
+        }
+    }
+
+    #[test]
+    fn args_are_percent_decoded() {
+        let args = parse_url_args("name=Jean+Dupont&reason=x%26y%20z").unwrap();
+
+        assert_eq!(args.get("name").map(|n| n.as_ref()), Some("Jean Dupont"));
+        assert_eq!(args.get("reason").map(|r| r.as_ref()), Some("x&y z"));
+        assert!(parse_url_args("uuid").is_err());
+        assert!(parse_url_args("reason=%2").is_err());
+        assert!(parse_url_args("reason=%zz").is_err());
+        assert!(parse_url_args("reason=%ff").is_err());
+    }
+}